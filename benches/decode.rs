@@ -0,0 +1,30 @@
+use rust_png_reader::decoder::decode_png;
+use rust_png_reader::parser::parse_png;
+use std::time::{Duration, Instant};
+
+const RUNS: u32 = 10;
+
+/// Time decoding the pixels of an already parsed image, compare the result of
+/// `cargo bench` with that of `cargo bench --features rayon`
+fn main() {
+    for path in ["examples/perfect2k.png", "examples/perfect4k.png"] {
+        let png_image = parse_png(&path.to_string()).unwrap();
+
+        let mut total = Duration::ZERO;
+        for _ in 0..RUNS {
+            let start = Instant::now();
+            let pixels = decode_png(&png_image).unwrap();
+            total += start.elapsed();
+            assert_eq!(pixels.len(), png_image.height as usize);
+        }
+
+        println!(
+            "{} ({}x{}): {:?} per decode, rayon: {}",
+            path,
+            png_image.width,
+            png_image.height,
+            total / RUNS,
+            cfg!(feature = "rayon")
+        );
+    }
+}
@@ -0,0 +1,47 @@
+import struct
+import unittest
+import zlib
+
+import rust_png_reader
+
+SIGNATURE = b"\x89PNG\r\n\x1a\n"
+
+
+def chunk(chunk_type, data):
+    crc = zlib.crc32(chunk_type + data)
+    return struct.pack(">I", len(data)) + chunk_type + data + struct.pack(">I", crc)
+
+
+def png(width, height, idat_data):
+    """8-bit grayscale png whose single IDAT chunk holds idat_data"""
+    ihdr = struct.pack(">IIBBBBB", width, height, 8, 0, 0, 0, 0)
+    return (
+        SIGNATURE
+        + chunk(b"IHDR", ihdr)
+        + chunk(b"IDAT", idat_data)
+        + chunk(b"IEND", b"")
+    )
+
+
+class ReadPngBytesTest(unittest.TestCase):
+    def test_decodes_valid_image(self):
+        data = png(2, 1, zlib.compress(bytes([0, 0, 255])))
+        self.assertEqual(
+            rust_png_reader.read_png_bytes(data),
+            [[(0, 0, 0, 255), (255, 255, 255, 255)]],
+        )
+
+    def test_truncated_idat_raises_corrupt_png_error(self):
+        compressed = zlib.compress(bytes([0, 0, 255]))
+        with self.assertRaises(rust_png_reader.CorruptPngError):
+            rust_png_reader.read_png_bytes(png(2, 1, compressed[:-6]))
+
+    def test_wrongly_sized_idat_raises_corrupt_png_error(self):
+        # The header claims two rows but the data only holds one
+        data = png(2, 2, zlib.compress(bytes([0, 0, 255])))
+        with self.assertRaises(rust_png_reader.CorruptPngError):
+            rust_png_reader.read_png_bytes(data)
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,248 @@
+use crate::decoder::{luminance, resize_bilinear};
+use crate::parser::{
+    calc_bytes_per_scanline_and_filt_offset, chunk_layout, read_metadata, Color, PngError, Result,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Statistics for a single color channel
+pub struct ChannelStat {
+    pub min: u8,
+    pub max: u8,
+    pub mean: f64,
+}
+
+/// Calculate the min, max and mean of the red, green, blue and alpha channels
+/// of the pixels in a single pass. All statistics are 0 for an empty image
+pub fn channel_stats(pixel_data: &[Vec<Color>]) -> [ChannelStat; 4] {
+    let mut min = [u8::MAX; 4];
+    let mut max = [u8::MIN; 4];
+    let mut sum = [0_u64; 4];
+    let mut count: u64 = 0;
+
+    for pixel in pixel_data.iter().flatten() {
+        let channels = [pixel.red, pixel.green, pixel.blue, pixel.alpha];
+        for (idx, &value) in channels.iter().enumerate() {
+            min[idx] = min[idx].min(value);
+            max[idx] = max[idx].max(value);
+            sum[idx] += value as u64;
+        }
+        count += 1;
+    }
+
+    if count == 0 {
+        return [ChannelStat {
+            min: 0,
+            max: 0,
+            mean: 0.0,
+        }; 4];
+    }
+
+    let stat = |idx: usize| ChannelStat {
+        min: min[idx],
+        max: max[idx],
+        mean: sum[idx] as f64 / count as f64,
+    };
+    [stat(0), stat(1), stat(2), stat(3)]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Rough classification of what an image contains
+pub enum ContentClass {
+    /// Many colors changing from pixel to pixel, better suited for JPEG
+    Photographic,
+    /// Larger flat areas of a limited number of colors, keep as PNG
+    Graphical,
+    /// Only a handful of colors, keep as PNG, possibly indexed
+    LineArt,
+}
+
+/// Return the number of distinct colors, alpha included, among the pixels
+pub fn unique_color_count(pixel_data: &[Vec<Color>]) -> usize {
+    let colors: std::collections::HashSet<[u8; 4]> = pixel_data
+        .iter()
+        .flatten()
+        .map(|pixel| [pixel.red, pixel.green, pixel.blue, pixel.alpha])
+        .collect();
+    colors.len()
+}
+
+/// Classify the content of an image using the number of unique colors and how
+/// often neighbouring pixels differ. Images with at most 4 colors are line
+/// art, images with more than 64 colors where at least half of all
+/// neighbouring pixels differ are photographic and anything else is graphical
+pub fn classify_content(pixel_data: &[Vec<Color>]) -> ContentClass {
+    let unique_colors = unique_color_count(pixel_data);
+    if unique_colors <= 4 {
+        return ContentClass::LineArt;
+    }
+
+    let mut pairs: usize = 0;
+    let mut differing: usize = 0;
+
+    for (row_idx, row) in pixel_data.iter().enumerate() {
+        for (col_idx, pixel) in row.iter().enumerate() {
+            // Compare against the pixel to the right and the pixel below
+            let neighbours = [
+                row.get(col_idx + 1),
+                pixel_data
+                    .get(row_idx + 1)
+                    .and_then(|below| below.get(col_idx)),
+            ];
+            for neighbour in neighbours.into_iter().flatten() {
+                pairs += 1;
+                if pixel != neighbour {
+                    differing += 1;
+                }
+            }
+        }
+    }
+
+    if unique_colors > 64 && differing * 2 >= pairs {
+        ContentClass::Photographic
+    } else {
+        ContentClass::Graphical
+    }
+}
+
+/// Compute the 64-bit difference hash (dHash) of an image. The image is
+/// resized to 9x8, and every bit tells whether a pixel is brighter than its
+/// right neighbour, row by row starting at the most significant bit. Similar
+/// images give hashes with a small Hamming distance, an empty image hashes to 0
+pub fn dhash(pixel_data: &[Vec<Color>]) -> u64 {
+    let small = resize_bilinear(pixel_data, 9, 8);
+
+    small
+        .iter()
+        .flat_map(|row| row.windows(2))
+        .fold(0, |hash, pair| {
+            (hash << 1) | (luminance(&pair[0]) > luminance(&pair[1])) as u64
+        })
+}
+
+/// Return the ratio of the size of the filtered image data, filter bytes
+/// included, to the total size of the compressed data in the IDAT chunks of
+/// the png file in bytes. Only the chunk layout and the IHDR chunk are read,
+/// nothing is decompressed
+pub fn compression_ratio(bytes: &[u8]) -> Result<f64> {
+    let info = read_metadata(bytes)?;
+    let (bytes_per_scanline, _) =
+        calc_bytes_per_scanline_and_filt_offset(info.width, info.bit_depth, &info.color_type)?;
+
+    // Every chunk span includes 12 bytes of length, type and checksum
+    let compressed: usize = chunk_layout(bytes)?
+        .into_iter()
+        .filter(|(name, _)| name == "IDAT")
+        .map(|(_, span)| span.len() - 12)
+        .sum();
+    if compressed == 0 {
+        return Err(PngError::WrongFormat("No IDAT data".to_string()));
+    }
+
+    let raw = info.height as u64 * (1 + bytes_per_scanline as u64);
+    Ok(raw as f64 / compressed as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    /// Return a width x height image whose colors change smoothly in both
+    /// directions, every pixel has a different color
+    fn gradient(width: usize, height: usize) -> Vec<Vec<Color>> {
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| Color {
+                        red: (x * 255 / (width - 1)) as u8,
+                        green: (y * 255 / (height - 1)) as u8,
+                        blue: ((x + y) * 255 / (width + height - 2)) as u8,
+                        alpha: 255,
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn channel_stats_of_gradient() {
+        let gradient: Vec<Color> = (0..=255)
+            .map(|value| Color {
+                red: value,
+                green: value,
+                blue: 255 - value,
+                alpha: 255,
+            })
+            .collect();
+        let [red, _, blue, alpha] = channel_stats(&[gradient]);
+
+        assert_eq!((red.min, red.max), (0, 255));
+        assert!((red.mean - 127.5).abs() < 1e-9);
+        assert_eq!(blue, red);
+        assert_eq!((alpha.min, alpha.max, alpha.mean), (255, 255, 255.0));
+    }
+
+    #[test]
+    fn classify_gradient_and_two_colors() {
+        assert_eq!(
+            classify_content(&gradient(16, 16)),
+            ContentClass::Photographic
+        );
+
+        let checkerboard: Vec<Vec<Color>> = (0..8)
+            .map(|y| {
+                (0..8)
+                    .map(|x| {
+                        let value = if (x + y) % 2 == 0 { 0 } else { 255 };
+                        Color {
+                            red: value,
+                            green: value,
+                            blue: value,
+                            alpha: 255,
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        assert_eq!(classify_content(&checkerboard), ContentClass::LineArt);
+    }
+
+    #[test]
+    fn dhash_of_similar_images_is_close() {
+        // Dark in the middle column and brighter towards both sides, so the
+        // hash has both set and unset bits
+        let original: Vec<Vec<Color>> = (0..32_i32)
+            .map(|y| {
+                (0..32_i32)
+                    .map(|x| {
+                        let value = ((x - 16).abs() * 14 + y) as u8;
+                        Color {
+                            red: value,
+                            green: value,
+                            blue: value,
+                            alpha: 255,
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut brightened = original.clone();
+        for (idx, pixel) in brightened.iter_mut().flatten().enumerate() {
+            if idx % 3 == 0 {
+                pixel.red = pixel.red.saturating_add(4);
+            }
+        }
+
+        let distance = (dhash(&original) ^ dhash(&brightened)).count_ones();
+        assert!(distance <= 4, "Hamming distance {}", distance);
+        assert_ne!(dhash(&original), 0);
+        assert_ne!(dhash(&original), u64::MAX);
+    }
+
+    #[test]
+    fn uniform_image_compresses_well() {
+        let filtered = vec![0; 64 * (1 + 64 * 3)];
+        let ratio = compression_ratio(&test_util::png(64, 64, 8, 2, &filtered, &[])).unwrap();
+        assert!(ratio > 50.0, "ratio {}", ratio);
+    }
+}
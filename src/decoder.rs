@@ -1,72 +1,1124 @@
-use crate::parser::{Color, ColorType, PngImage};
+use crate::parser::{
+    allowed_bit_depths, bits_per_pixel, calc_bytes_per_scanline_and_filt_offset, parse_compressed,
+    parse_png_bytes, parse_png_with_trailing, read_metadata, reconstruct_scanline, zlib_reader,
+    Color, ColorType, ParseOptions, PngError, PngImage, Result, Transfer,
+};
 use colored::Colorize;
+use std::io::Read;
+
+#[derive(Debug)]
+/// A decoded image with its pixels stored row by row in a single vector
+pub struct DecodedImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color>,
+}
+
+impl DecodedImage {
+    /// Decode a parsed png image, returns an error if the format is not supported
+    pub fn from_png(png_image: &PngImage) -> Result<DecodedImage> {
+        let rows = decode_png(png_image)?;
+
+        let decoded = DecodedImage {
+            width: png_image.width,
+            height: png_image.height,
+            pixels: rows.into_rows().into_iter().flatten().collect(),
+        };
+        debug_assert!(decoded.is_dimensionally_consistent());
+        Ok(decoded)
+    }
+
+    /// Return true if the number of pixels matches the width and height
+    pub fn is_dimensionally_consistent(&self) -> bool {
+        self.pixels.len() == self.width as usize * self.height as usize
+    }
+
+    /// Return a 64-bit FNV-1a hash of the dimensions and the RGBA bytes of
+    /// every pixel. The hash is stable across runs and platforms, so it can
+    /// be used as a cache key for the decoded content
+    pub fn content_hash(&self) -> u64 {
+        const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+        const FNV_PRIME: u64 = 0x100000001b3;
+
+        let dimensions = [self.width.to_be_bytes(), self.height.to_be_bytes()];
+        let pixels = self
+            .pixels
+            .iter()
+            .map(|pixel| [pixel.red, pixel.green, pixel.blue, pixel.alpha]);
+
+        dimensions
+            .into_iter()
+            .chain(pixels)
+            .flatten()
+            .fold(FNV_OFFSET_BASIS, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+            })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Decoded pixels stored as one vector per row, where every row is guaranteed
+/// to be equally wide. Derefs to a slice of rows for read-only access
+pub struct PixelGrid {
+    width: usize,
+    rows: Vec<Vec<Color>>,
+}
+
+impl PixelGrid {
+    /// Construct a grid from rows of pixels, returns an error if the rows are
+    /// of different lengths
+    pub fn new(rows: Vec<Vec<Color>>) -> Result<PixelGrid> {
+        let width = rows.first().map_or(0, |row| row.len());
+
+        match rows.iter().position(|row| row.len() != width) {
+            Some(row_idx) => Err(PngError::WrongFormat(format!(
+                "Row {} has {} pixels, expected {}",
+                row_idx,
+                rows[row_idx].len(),
+                width
+            ))),
+            None => Ok(PixelGrid { width, rows }),
+        }
+    }
+
+    /// Return the number of pixels in every row
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Return the number of rows
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Return the pixels of row y, or None if y is outside of the grid
+    pub fn row(&self, y: usize) -> Option<&[Color]> {
+        self.rows.get(y).map(|row| row.as_slice())
+    }
+
+    /// Consume the grid and return its rows
+    pub fn into_rows(self) -> Vec<Vec<Color>> {
+        self.rows
+    }
+}
+
+impl std::ops::Deref for PixelGrid {
+    type Target = [Vec<Color>];
+
+    fn deref(&self) -> &Self::Target {
+        &self.rows
+    }
+}
+
+#[derive(Debug, Default)]
+/// Options controlling how the pixels of a parsed png image are decoded
+pub struct DecodeOptions {
+    /// Read 16-bit samples as little-endian. The png specification mandates
+    /// big-endian samples, this is a non-standard accommodation for files
+    /// written by known-broken encoders and should be left off otherwise
+    pub assume_le_16bit: bool,
+    /// What to do with palette indices that point past the end of the palette
+    pub palette_oob: PaletteOob,
+    /// Convert the color channels of images with a gAMA chunk for display on
+    /// a standard monitor with gamma 2.2. Images with an sRGB chunk or without
+    /// gAMA are left as they are since they already suit such a display
+    pub apply_gamma: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+/// Handling of palette indices outside of the palette of an indexed image
+pub enum PaletteOob {
+    /// Fail decoding with an error
+    #[default]
+    Error,
+    /// Use the given color for every out of range index, which lets damaged
+    /// files still render
+    Fill(Color),
+}
+
+#[derive(Debug)]
+/// Palette indices of an indexed color image kept packed as in the png data,
+/// so a 1-bit image uses one bit per pixel instead of a full Color
+pub struct PackedIndices {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    bytes_per_scanline: usize,
+    data: Vec<u8>,
+}
+
+impl PackedIndices {
+    /// Return the palette index of the pixel at column x and row y. Panics if
+    /// the position is outside of the image
+    pub fn index_at(&self, x: u32, y: u32) -> u8 {
+        assert!(x < self.width && y < self.height, "Pixel out of bounds");
+
+        let bit_offset = x as usize * self.bit_depth as usize;
+        let byte = self.data[y as usize * self.bytes_per_scanline + bit_offset / 8];
+        let shift = 8 - self.bit_depth as usize - bit_offset % 8;
+        let mask = ((1_u16 << self.bit_depth) - 1) as u8;
+
+        byte >> shift & mask
+    }
+}
+
+/// Return the palette and the packed palette indices of an indexed color
+/// image without expanding every pixel to a Color
+pub fn decode_indexed_packed(png_image: &PngImage) -> Result<(Vec<Color>, PackedIndices)> {
+    let palette = match (&png_image.color_type, &png_image.palette) {
+        (ColorType::IndexedColor, Some(palette)) => palette.clone(),
+        (ColorType::IndexedColor, None) => {
+            return Err(PngError::WrongFormat(
+                "Indexed image has no palette".to_string(),
+            ))
+        }
+        _ => {
+            return Err(PngError::NotSupported(format!(
+                "Packed indices for {:?}",
+                png_image.color_type
+            )))
+        }
+    };
+
+    let bits_per_scanline = png_image.width as usize * png_image.bit_depth as usize;
+    let indices = PackedIndices {
+        width: png_image.width,
+        height: png_image.height,
+        bit_depth: png_image.bit_depth,
+        bytes_per_scanline: bits_per_scanline.div_ceil(8),
+        data: png_image.data.clone(),
+    };
+    Ok((palette, indices))
+}
+
+/// Return the palette index of every pixel of an indexed color image, one
+/// vector per row, together with the palette. Returns an error if the image
+/// is not indexed
+pub fn decode_indexed(png_image: &PngImage) -> Result<(Vec<Vec<u8>>, Vec<Color>)> {
+    let (palette, indices) = decode_indexed_packed(png_image)?;
+
+    let rows = (0..indices.height)
+        .map(|y| (0..indices.width).map(|x| indices.index_at(x, y)).collect())
+        .collect();
+    Ok((rows, palette))
+}
 
 /// Decodes a png image and return the result using one of the decoder functions.
-/// If the png format is not supported then None is returned
-pub fn decode_png(png_image: &PngImage) -> Option<Vec<Vec<Color>>> {
-    match png_image.color_type {
-        ColorType::Truecolor | ColorType::TrueColorWithAlpha => {
-            Some(png_truecolor_to_pixels(png_image))
+/// If the png format is not supported or the decoded pixels do not match the
+/// dimensions of the image an error is returned
+pub fn decode_png(png_image: &PngImage) -> Result<PixelGrid> {
+    decode_png_with_options(png_image, &DecodeOptions::default())
+}
+
+/// Same as decode_png but with control over the decoding through options
+pub fn decode_png_with_options(png_image: &PngImage, options: &DecodeOptions) -> Result<PixelGrid> {
+    let (bytes_per_scanline, _) = calc_bytes_per_scanline_and_filt_offset(
+        png_image.width,
+        png_image.bit_depth,
+        &png_image.color_type,
+    )?;
+
+    let mut res = decode_scanlines(png_image, bytes_per_scanline, options)?;
+
+    check_dimensions(png_image, &res)?;
+
+    if let (true, Transfer::Gamma(gamma)) = (options.apply_gamma, png_image.effective_transfer()) {
+        let table = gamma_correction_table(gamma);
+        for pixel in res.iter_mut().flatten() {
+            pixel.red = table[pixel.red as usize];
+            pixel.green = table[pixel.green as usize];
+            pixel.blue = table[pixel.blue as usize];
         }
-        ColorType::IndexedColor => Some(png_indexed_color_to_pixels(png_image)),
-        ColorType::Grayscale => Some(png_grayscale_to_pixels(png_image)),
-        _ => None,
     }
+    PixelGrid::new(res)
+}
+
+#[derive(Debug)]
+/// Iterator decoding the scanlines of a parsed png image one at a time, so
+/// only the pixels of the current row are held in memory. Yields an error for
+/// a row that cannot be decoded, such as one using an out of range palette
+/// index
+pub struct ScanlineIter<'a> {
+    png_image: &'a PngImage,
+    scanlines: std::slice::Chunks<'a, u8>,
+}
+
+impl<'a> ScanlineIter<'a> {
+    /// Create an iterator over the rows of an image, returns an error if the
+    /// color type and bit depth of the image are an invalid combination
+    pub fn new(png_image: &'a PngImage) -> Result<ScanlineIter<'a>> {
+        let (bytes_per_scanline, _) = calc_bytes_per_scanline_and_filt_offset(
+            png_image.width,
+            png_image.bit_depth,
+            &png_image.color_type,
+        )?;
+
+        Ok(ScanlineIter {
+            png_image,
+            scanlines: png_image.data.chunks(bytes_per_scanline),
+        })
+    }
+}
+
+impl Iterator for ScanlineIter<'_> {
+    type Item = Result<Vec<Color>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let scanline = self.scanlines.next()?;
+        Some(decode_scanline(
+            self.png_image,
+            scanline,
+            &DecodeOptions::default(),
+        ))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.scanlines.size_hint()
+    }
+}
+
+#[cfg(not(feature = "rayon"))]
+/// Decode every reconstructed scanline of a parsed png image in order
+fn decode_scanlines(
+    png_image: &PngImage,
+    bytes_per_scanline: usize,
+    options: &DecodeOptions,
+) -> Result<Vec<Vec<Color>>> {
+    png_image
+        .data
+        .chunks(bytes_per_scanline)
+        .map(|scanline| decode_scanline(png_image, scanline, options))
+        .collect()
+}
+
+#[cfg(feature = "rayon")]
+/// Decode every reconstructed scanline of a parsed png image on the rayon
+/// thread pool. Unlike reconstruction, decoding a scanline does not depend on
+/// the previous one, so the scanlines are decoded concurrently and collected
+/// in order
+fn decode_scanlines(
+    png_image: &PngImage,
+    bytes_per_scanline: usize,
+    options: &DecodeOptions,
+) -> Result<Vec<Vec<Color>>> {
+    use rayon::prelude::*;
+
+    png_image
+        .data
+        .par_chunks(bytes_per_scanline)
+        .map(|scanline| decode_scanline(png_image, scanline, options))
+        .collect()
+}
+
+/// Gamma of the display assumed when applying gamma correction
+const DISPLAY_GAMMA: f64 = 2.2;
+
+/// Build a lookup table converting 8-bit samples stored with the given
+/// encoding gamma, as found in gAMA, to samples for a display with gamma
+/// DISPLAY_GAMMA, using out = 255 * (in / 255)^(1 / (gamma * DISPLAY_GAMMA))
+fn gamma_correction_table(gamma: f64) -> [u8; 256] {
+    let exponent = 1.0 / (gamma * DISPLAY_GAMMA);
+    let mut table = [0_u8; 256];
+    for (sample, entry) in table.iter_mut().enumerate() {
+        *entry = (255.0 * (sample as f64 / 255.0).powf(exponent)).round() as u8;
+    }
+    table
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A single channel of the pixels of an image
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+    Alpha,
+    /// The sample of a grayscale image
+    Gray,
+}
+
+/// Decode a single channel of a png image into one byte per pixel. For
+/// truecolor and grayscale images only the bytes of the requested channel are
+/// read, 16-bit samples are reduced to their most significant byte. The red,
+/// green and blue channels of a grayscale image are all the gray sample and
+/// the alpha channel of an image without alpha is 255. Requesting the gray
+/// channel of a color image returns an error
+pub fn decode_channel(png_image: &PngImage, channel: Channel) -> Result<Vec<Vec<u8>>> {
+    let channels = png_image.channels();
+    let indexed = matches!(png_image.color_type, ColorType::IndexedColor);
+    let sample_idx = match (channels, channel) {
+        (1, Channel::Alpha) => None,
+        (1, _) if !indexed => Some(0),
+        (2, Channel::Alpha) => Some(1),
+        (2, _) => Some(0),
+        (_, Channel::Gray) => {
+            return Err(PngError::NotSupported(format!(
+                "Gray channel of {:?}",
+                png_image.color_type
+            )))
+        }
+        (_, Channel::Red) => Some(0),
+        (_, Channel::Green) => Some(1),
+        (_, Channel::Blue) => Some(2),
+        (4, Channel::Alpha) => Some(3),
+        (_, Channel::Alpha) => None,
+    };
+
+    if matches!(png_image.color_type, ColorType::Grayscale) && sample_idx.is_some() {
+        return decode_grayscale_single(png_image);
+    }
+
+    // Palette indices have to be looked up anyway, so take the channel from
+    // the fully decoded pixels. The same goes for the alpha of an image whose
+    // tRNS chunk makes a single color transparent
+    let keyed_alpha = matches!(channel, Channel::Alpha) && color_key(png_image).is_some();
+    if matches!(png_image.color_type, ColorType::IndexedColor) || keyed_alpha {
+        let pick = |pixel: &Color| match channel {
+            Channel::Red | Channel::Gray => pixel.red,
+            Channel::Green => pixel.green,
+            Channel::Blue => pixel.blue,
+            Channel::Alpha => pixel.alpha,
+        };
+        return Ok(decode_png(png_image)?
+            .iter()
+            .map(|row| row.iter().map(pick).collect())
+            .collect());
+    }
+
+    let (bytes_per_scanline, _) = calc_bytes_per_scanline_and_filt_offset(
+        png_image.width,
+        png_image.bit_depth,
+        &png_image.color_type,
+    )?;
+    let width = png_image.width as usize;
+    let bytes_per_channel = png_image.bit_depth as usize / 8;
+
+    Ok(png_image
+        .data
+        .chunks(bytes_per_scanline)
+        .map(|scanline| match sample_idx {
+            Some(idx) => scanline
+                .iter()
+                .skip(idx * bytes_per_channel)
+                .step_by(bytes_per_channel * channels)
+                .take(width)
+                .copied()
+                .collect(),
+            None => vec![255; width],
+        })
+        .collect())
+}
+
+/// Decode a grayscale image into one byte per pixel instead of expanding
+/// every sample to a Color. Samples narrower than a byte are scaled to the
+/// full 8-bit range and 16-bit samples are reduced to their most significant
+/// byte. Returns an error for any other color type
+pub fn decode_grayscale_single(png_image: &PngImage) -> Result<Vec<Vec<u8>>> {
+    if !matches!(png_image.color_type, ColorType::Grayscale) {
+        return Err(PngError::NotSupported(format!(
+            "Single channel decoding of {:?}",
+            png_image.color_type
+        )));
+    }
+
+    let (bytes_per_scanline, _) = calc_bytes_per_scanline_and_filt_offset(
+        png_image.width,
+        png_image.bit_depth,
+        &png_image.color_type,
+    )?;
+    let width = png_image.width as usize;
+    let bit_depth = png_image.bit_depth as usize;
+
+    Ok(png_image
+        .data
+        .chunks(bytes_per_scanline)
+        .map(|scanline| match bit_depth {
+            16 => scanline.iter().step_by(2).copied().collect(),
+            8 => scanline.to_vec(),
+            _ => {
+                let mask = ((1_u16 << bit_depth) - 1) as u8;
+                (0..width)
+                    .map(|x| {
+                        let bit_offset = x * bit_depth;
+                        let shift = 8 - bit_depth - bit_offset % 8;
+                        (scanline[bit_offset / 8] >> shift & mask) * (255 / mask)
+                    })
+                    .collect()
+            }
+        })
+        .collect())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Order of the channels of every pixel in a flat buffer
+pub enum ChannelOrder {
+    Rgba,
+    Bgra,
+    Argb,
+    Abgr,
+}
+
+/// Decode a png image into a flat buffer with four bytes per pixel in the
+/// given channel order, return the width, the height and the buffer
+pub fn decode_flat_ordered(
+    png_image: &PngImage,
+    order: ChannelOrder,
+) -> Result<(u32, u32, Vec<u8>)> {
+    let (bytes_per_scanline, _) = calc_bytes_per_scanline_and_filt_offset(
+        png_image.width,
+        png_image.bit_depth,
+        &png_image.color_type,
+    )?;
+
+    let mut res: Vec<u8> =
+        Vec::with_capacity(png_image.width as usize * png_image.height as usize * 4);
+
+    for scanline in png_image.data.chunks(bytes_per_scanline) {
+        let pixels = decode_scanline(png_image, scanline, &DecodeOptions::default())?;
+        for pixel in pixels {
+            let (red, green, blue, alpha) = (pixel.red, pixel.green, pixel.blue, pixel.alpha);
+            res.extend_from_slice(&match order {
+                ChannelOrder::Rgba => [red, green, blue, alpha],
+                ChannelOrder::Bgra => [blue, green, red, alpha],
+                ChannelOrder::Argb => [alpha, red, green, blue],
+                ChannelOrder::Abgr => [alpha, blue, green, red],
+            });
+        }
+    }
+
+    if res.len() != png_image.width as usize * png_image.height as usize * 4 {
+        return Err(PngError::WrongFormat(format!(
+            "Decoded {} pixels, expected {}",
+            res.len() / 4,
+            png_image.width as usize * png_image.height as usize
+        )));
+    }
+    Ok((png_image.width, png_image.height, res))
+}
+
+impl PngImage {
+    /// Decode the image into a tightly packed buffer with four bytes per pixel
+    /// in RGBA order for every color type, alpha is 255 if the image has none
+    pub fn to_rgba_bytes(&self) -> Result<Vec<u8>> {
+        let (_, _, res) = decode_flat_ordered(self, ChannelOrder::Rgba)?;
+        Ok(res)
+    }
+
+    /// Decode only the pixel at column x and row y, which is much cheaper than
+    /// decoding the whole image to look up a few pixels. Returns an error if
+    /// the position is outside of the image
+    pub fn pixel(&self, x: u32, y: u32) -> Result<Color> {
+        if x >= self.width || y >= self.height {
+            return Err(PngError::WrongFormat(format!(
+                "Pixel ({}, {}) is outside of the {}x{} image",
+                x, y, self.width, self.height
+            )));
+        }
+        let (bytes_per_scanline, _) =
+            calc_bytes_per_scanline_and_filt_offset(self.width, self.bit_depth, &self.color_type)?;
+        let bits_per_pixel = bits_per_pixel(self.bit_depth, &self.color_type);
+
+        let bit_offset = y as usize * bytes_per_scanline * 8 + x as usize * bits_per_pixel;
+        let bytes = self
+            .data
+            .get(bit_offset / 8..(bit_offset + bits_per_pixel).div_ceil(8))
+            .ok_or(PngError::TruncatedData)?;
+
+        // Move a packed pixel to the start of its own byte so that it decodes
+        // as the first pixel of a scanline, clearing the pixels after it
+        let shifted;
+        let pixel_bytes = match bits_per_pixel {
+            1..=7 => {
+                shifted = [bytes[0] << (bit_offset % 8) & !(0xFF_u8 >> bits_per_pixel)];
+                &shifted[..]
+            }
+            _ => bytes,
+        };
+
+        decode_scanline(self, pixel_bytes, &DecodeOptions::default())?
+            .first()
+            .copied()
+            .ok_or(PngError::TruncatedData)
+    }
+}
+
+/// Decode a png image into a single flat buffer of 8-bit samples stored row by
+/// row, without allocating a vector per row. The number of channels follows
+/// the color type: 1 for grayscale, 3 for truecolor and indexed color, plus
+/// one for alpha if the color type has an alpha channel or the image has a
+/// tRNS chunk that applies to it. The alpha of grayscale and truecolor images
+/// is 0 for pixels of the tRNS color and 255 otherwise. Return the buffer, the
+/// width, the height and the number of channels
+pub fn decode_png_flat(png_image: &PngImage) -> Result<(Vec<u8>, usize, usize, usize)> {
+    let channels = match png_image.color_type {
+        ColorType::Grayscale => 1 + color_key(png_image).is_some() as usize,
+        ColorType::GrayScaleWithAlpha => 2,
+        ColorType::Truecolor => 3 + color_key(png_image).is_some() as usize,
+        ColorType::IndexedColor => 3 + png_image.transparency().is_some() as usize,
+        ColorType::TrueColorWithAlpha => 4,
+    };
+    let (bytes_per_scanline, _) = calc_bytes_per_scanline_and_filt_offset(
+        png_image.width,
+        png_image.bit_depth,
+        &png_image.color_type,
+    )?;
+    let (width, height) = (png_image.width as usize, png_image.height as usize);
+
+    let mut res: Vec<u8> = Vec::with_capacity(width * height * channels);
+
+    for scanline in png_image.data.chunks(bytes_per_scanline) {
+        let pixels = decode_scanline(png_image, scanline, &DecodeOptions::default())?;
+        for pixel in pixels {
+            let samples = [pixel.red, pixel.green, pixel.blue, pixel.alpha];
+            match channels {
+                1 => res.push(pixel.red),
+                2 => res.extend_from_slice(&[pixel.red, pixel.alpha]),
+                3 => res.extend_from_slice(&samples[..3]),
+                _ => res.extend_from_slice(&samples),
+            }
+        }
+    }
+
+    if res.len() != width * height * channels {
+        return Err(PngError::WrongFormat(format!(
+            "Decoded {} pixels, expected {}",
+            res.len() / channels,
+            width * height
+        )));
+    }
+    Ok((res, width, height, channels))
+}
+
+/// Decode a png image into one [red, green, blue, alpha] array of floats per
+/// pixel, where every channel is the stored sample divided by the largest
+/// value of the bit depth (255.0 for 8-bit, 65535.0 for 16-bit). No transfer
+/// function or gamma conversion is applied. Return the width, the height and
+/// the pixels
+pub fn decode_normalized_f32(png_image: &PngImage) -> Result<(u32, u32, Vec<[f32; 4]>)> {
+    // Palette colors and sub-byte grayscale samples are already scaled to the
+    // full 8-bit range by the decoder
+    if png_image.bit_depth != 16 || png_image.color_type == ColorType::IndexedColor {
+        let pixels = decode_png(png_image)?
+            .iter()
+            .flatten()
+            .map(|pixel| {
+                [pixel.red, pixel.green, pixel.blue, pixel.alpha].map(|value| value as f32 / 255.0)
+            })
+            .collect();
+        return Ok((png_image.width, png_image.height, pixels));
+    }
+
+    let key = color_key(png_image);
+    let pixels: Vec<[f32; 4]> = png_image
+        .data
+        .chunks(png_image.bytes_per_pixel())
+        .map(|pixel| {
+            let raw: Vec<u16> = pixel
+                .chunks(2)
+                .map(|sample| u16::from_be_bytes([sample[0], sample[1]]))
+                .collect();
+            let key_alpha = match key.as_ref() == Some(&raw) {
+                true => 0.0,
+                false => 1.0,
+            };
+            let samples: Vec<f32> = raw.iter().map(|&sample| sample as f32 / 65535.0).collect();
+            match samples[..] {
+                [gray] => [gray, gray, gray, key_alpha],
+                [gray, alpha] => [gray, gray, gray, alpha],
+                [red, green, blue] => [red, green, blue, key_alpha],
+                [red, green, blue, alpha] => [red, green, blue, alpha],
+                _ => unreachable!(),
+            }
+        })
+        .collect();
+
+    if pixels.len() != png_image.width as usize * png_image.height as usize {
+        return Err(PngError::WrongFormat(format!(
+            "Decoded {} pixels, expected {}",
+            pixels.len(),
+            png_image.width as usize * png_image.height as usize
+        )));
+    }
+    Ok((png_image.width, png_image.height, pixels))
+}
+
+/// Decode a png image contained in bytes one scanline at a time and call
+/// on_row with the row index and the pixels of every row. The image data is
+/// decompressed while it is decoded and only the current and the previous
+/// scanline are kept besides the compressed data, so neither the decompressed
+/// data nor the full image is ever materialized. Interlaced images are not
+/// supported
+pub fn decode_streaming<F: FnMut(u32, &[Color])>(bytes: &[u8], mut on_row: F) -> Result<()> {
+    let (png_image, idat_data, _) = parse_compressed(bytes, &ParseOptions::default())?;
+    if png_image.is_interlaced() {
+        return Err(PngError::NotSupported(
+            "Streaming decoding of Adam7 interlaced images".to_string(),
+        ));
+    }
+    let (bytes_per_scanline, filt_offset) = calc_bytes_per_scanline_and_filt_offset(
+        png_image.width,
+        png_image.bit_depth,
+        &png_image.color_type,
+    )?;
+
+    let mut decoder = zlib_reader(&idat_data)?;
+    let mut prev_scanline: Option<Vec<u8>> = None;
+
+    // Every scanline is preceded by its filter byte
+    let mut filtered: Vec<u8> = vec![0; bytes_per_scanline + 1];
+    for row_idx in 0..png_image.height {
+        decoder
+            .read_exact(&mut filtered)
+            .map_err(|error| match error.kind() {
+                std::io::ErrorKind::UnexpectedEof => PngError::TruncatedData,
+                _ => PngError::DecompressionFailed,
+            })?;
+        let scanline =
+            reconstruct_scanline(&filtered, prev_scanline.as_deref(), filt_offset, None)?;
+        let pixels = decode_scanline(&png_image, &scanline, &DecodeOptions::default())?;
+
+        if pixels.len() != png_image.width as usize {
+            return Err(PngError::WrongFormat(format!(
+                "Decoded row {} has {} pixels, expected {}",
+                row_idx,
+                pixels.len(),
+                png_image.width
+            )));
+        }
+        on_row(row_idx, &pixels);
+
+        prev_scanline = Some(scanline);
+    }
+
+    // Read the rest of the stream so that its checksum is verified
+    std::io::copy(&mut decoder, &mut std::io::sink()).map_err(|_| PngError::DecompressionFailed)?;
+    Ok(())
+}
+
+/// Decode the png image in bytes into an existing DecodedImage, overwriting
+/// its width, height and pixels. The pixel vector of the target is reused and
+/// only reallocated if the new image has more pixels than it has capacity
+/// for, so pooled images stop allocating once they have seen the largest
+/// image. If decoding fails the contents of the target are unspecified
+pub fn decode_into_image(bytes: &[u8], target: &mut DecodedImage) -> Result<()> {
+    let info = read_metadata(bytes)?;
+
+    target.pixels.clear();
+    target
+        .pixels
+        .reserve(info.width as usize * info.height as usize);
+
+    // The passes of an interlaced image cover the whole image, so it cannot
+    // be decoded row by row
+    if info.interlaced {
+        let rows = decode_png(&parse_png_bytes(bytes)?)?;
+        target.pixels.extend(rows.iter().flatten());
+    } else {
+        decode_streaming(bytes, |_, row| target.pixels.extend_from_slice(row))?;
+    }
+
+    target.width = info.width;
+    target.height = info.height;
+    debug_assert!(target.is_dimensionally_consistent());
+    Ok(())
+}
+
+/// Verify that a decoded pixel grid has one row per scanline and that every
+/// row is exactly as wide as the image
+fn check_dimensions(png_image: &PngImage, pixel_data: &[Vec<Color>]) -> Result<()> {
+    if pixel_data.len() != png_image.height as usize {
+        return Err(PngError::WrongFormat(format!(
+            "Decoded {} rows, expected {}",
+            pixel_data.len(),
+            png_image.height
+        )));
+    }
+    match pixel_data
+        .iter()
+        .position(|row| row.len() != png_image.width as usize)
+    {
+        Some(row_idx) => Err(PngError::WrongFormat(format!(
+            "Decoded row {} has {} pixels, expected {}",
+            row_idx,
+            pixel_data[row_idx].len(),
+            png_image.width
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Decode every png image in a buffer containing one or more png files stored
+/// back to back. Padding made up of whitespace or null bytes after the last
+/// image is ignored
+pub fn decode_all(bytes: &[u8]) -> Result<Vec<DecodedImage>> {
+    let mut res: Vec<DecodedImage> = Vec::new();
+    let mut rest = bytes;
+
+    while !rest
+        .iter()
+        .all(|byte| byte.is_ascii_whitespace() || *byte == 0)
+    {
+        let (png_image, trailing) = parse_png_with_trailing(rest)?;
+        res.push(DecodedImage::from_png(&png_image)?);
+        rest = trailing;
+    }
+    Ok(res)
 }
 
 /// Uses the Colorize crate to print a png image to the terminal as RGB.
 /// Requires a terminal with truecolor support
-pub fn print_png(pixel_data: &Vec<Vec<Color>>) {
+pub fn print_png(pixel_data: &[Vec<Color>]) {
+    for row in render_png_styled(pixel_data) {
+        println!("{}", row);
+    }
+}
+
+/// Uses the Colorize crate to render a png image as one string per row, where
+/// every pixel is a space with an ANSI truecolor background
+pub fn render_png_styled(pixel_data: &[Vec<Color>]) -> Vec<String> {
+    pixel_data
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|pixel| {
+                    " ".on_truecolor(pixel.red, pixel.green, pixel.blue)
+                        .to_string()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Alpha-composite every pixel over the background color bg and return the
+/// resulting opaque pixels. Uses straight (non-premultiplied) alpha, ie
+/// out = src * alpha + bg * (1 - alpha) for each channel
+pub fn composite_over(pixel_data: &[Vec<Color>], bg: Color) -> Vec<Vec<Color>> {
+    let blend = |src: u8, bg: u8, alpha: u8| -> u8 {
+        let (src, bg, alpha) = (src as u32, bg as u32, alpha as u32);
+        ((src * alpha + bg * (255 - alpha) + 127) / 255) as u8
+    };
+
+    pixel_data
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|pixel| Color {
+                    red: blend(pixel.red, bg.red, pixel.alpha),
+                    green: blend(pixel.green, bg.green, pixel.alpha),
+                    blue: blend(pixel.blue, bg.blue, pixel.alpha),
+                    alpha: 255,
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Alpha-composite the decoded pixels of png_image over the background color
+/// suggested by its bKGD chunk, or over fallback if the file has none. See
+/// composite_over for how the pixels are blended
+pub fn flatten_over_background(
+    png_image: &PngImage,
+    pixel_data: &[Vec<Color>],
+    fallback: Color,
+) -> Vec<Vec<Color>> {
+    let bg = png_image.background().unwrap_or(fallback);
+    composite_over(pixel_data, Color { alpha: 255, ..bg })
+}
+
+/// Luminance of a pixel using the ITU-R BT.601 weights
+pub(crate) fn luminance(pixel: &Color) -> f64 {
+    0.299 * pixel.red as f64 + 0.587 * pixel.green as f64 + 0.114 * pixel.blue as f64
+}
+
+/// Convert every pixel to gray by setting its red, green and blue channels to
+/// its rounded luminance, alpha is kept as it is
+pub fn to_grayscale(pixel_data: &[Vec<Color>]) -> Vec<Vec<Color>> {
+    pixel_data
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|pixel| {
+                    let gray = luminance(pixel).round() as u8;
+                    Color {
+                        red: gray,
+                        green: gray,
+                        blue: gray,
+                        alpha: pixel.alpha,
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Reverse the order of the rows of an image in place, turning it upside down
+pub fn flip_vertical(pixel_data: &mut [Vec<Color>]) {
+    pixel_data.reverse();
+}
+
+/// Reverse the pixels of every row of an image in place, mirroring it from
+/// left to right
+pub fn flip_horizontal(pixel_data: &mut [Vec<Color>]) {
     for row in pixel_data {
-        for pixel in row {
-            print!("{}", " ".on_truecolor(pixel.red, pixel.green, pixel.blue));
+        row.reverse();
+    }
+}
+
+/// Return the width x height region of an image whose top left pixel is at
+/// column x and row y. A width or height of zero gives an empty image, a
+/// region extending past the bottom or the right edge of the image returns an
+/// error
+pub fn crop(
+    pixel_data: &[Vec<Color>],
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+) -> Result<Vec<Vec<Color>>> {
+    if width == 0 || height == 0 {
+        return Ok(Vec::new());
+    }
+
+    let out_of_bounds = || {
+        PngError::WrongFormat(format!(
+            "Crop region {}x{} at ({}, {}) is outside of the image",
+            width, height, x, y
+        ))
+    };
+    let rows = y
+        .checked_add(height)
+        .and_then(|end| pixel_data.get(y..end))
+        .ok_or_else(out_of_bounds)?;
+
+    rows.iter()
+        .map(|row| {
+            x.checked_add(width)
+                .and_then(|end| row.get(x..end))
+                .map(|pixels| pixels.to_vec())
+                .ok_or_else(out_of_bounds)
+        })
+        .collect()
+}
+
+/// Resize an image to new_width x new_height by bilinear interpolation of all
+/// four channels. An empty image or a target size of zero gives an empty image
+pub fn resize_bilinear(
+    pixel_data: &[Vec<Color>],
+    new_width: usize,
+    new_height: usize,
+) -> Vec<Vec<Color>> {
+    let height = pixel_data.len();
+    let width = pixel_data.first().map_or(0, |row| row.len());
+    if width == 0 || height == 0 || new_width == 0 || new_height == 0 {
+        return Vec::new();
+    }
+
+    // Map the center of a target pixel onto the source grid, return the two
+    // source positions to interpolate between and the weight of the second
+    let source_position = |target: usize, target_len: usize, source_len: usize| {
+        let pos = (target as f64 + 0.5) * source_len as f64 / target_len as f64 - 0.5;
+        let pos = pos.clamp(0.0, (source_len - 1) as f64);
+        let low = pos.floor() as usize;
+        (low, (low + 1).min(source_len - 1), pos - low as f64)
+    };
+
+    let interpolate = |corners: [u8; 4], tx: f64, ty: f64| -> u8 {
+        let [tl, tr, bl, br] = corners.map(|value| value as f64);
+        let top = tl + (tr - tl) * tx;
+        let bottom = bl + (br - bl) * tx;
+        (top + (bottom - top) * ty).round() as u8
+    };
+
+    (0..new_height)
+        .map(|y| {
+            let (y0, y1, ty) = source_position(y, new_height, height);
+            (0..new_width)
+                .map(|x| {
+                    let (x0, x1, tx) = source_position(x, new_width, width);
+                    let corners = [
+                        pixel_data[y0][x0],
+                        pixel_data[y0][x1],
+                        pixel_data[y1][x0],
+                        pixel_data[y1][x1],
+                    ];
+                    Color {
+                        red: interpolate(corners.map(|c| c.red), tx, ty),
+                        green: interpolate(corners.map(|c| c.green), tx, ty),
+                        blue: interpolate(corners.map(|c| c.blue), tx, ty),
+                        alpha: interpolate(corners.map(|c| c.alpha), tx, ty),
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Resize an image to new_width x new_height by copying the source pixel
+/// nearest to the center of every target pixel. Faster but blockier than
+/// resize_bilinear, an empty image or a target size of zero gives an empty
+/// image
+pub fn resize_nearest(
+    pixel_data: &[Vec<Color>],
+    new_width: usize,
+    new_height: usize,
+) -> Vec<Vec<Color>> {
+    let height = pixel_data.len();
+    let width = pixel_data.first().map_or(0, |row| row.len());
+    if width == 0 || height == 0 || new_width == 0 || new_height == 0 {
+        return Vec::new();
+    }
+
+    // Index of the source pixel containing the center of a target pixel
+    let source_index = |target: usize, target_len: usize, source_len: usize| {
+        ((2 * target + 1) * source_len / (2 * target_len)).min(source_len - 1)
+    };
+
+    (0..new_height)
+        .map(|y| {
+            let row = &pixel_data[source_index(y, new_height, height)];
+            (0..new_width)
+                .map(|x| row[source_index(x, new_width, width)])
+                .collect()
+        })
+        .collect()
+}
+
+/// Return the offset of the most significant byte within a sample. For 16-bit
+/// samples this is the first byte unless the options say the samples are
+/// little-endian, the decoders keep the most significant byte of each sample
+fn sample_msb_offset(png_file: &PngImage, options: &DecodeOptions) -> usize {
+    if png_file.bit_depth == 16 && options.assume_le_16bit {
+        1
+    } else {
+        0
+    }
+}
+
+/// Return the samples of the single fully transparent color given by the tRNS
+/// chunk of a grayscale or truecolor image, or None if there is none. The
+/// samples are compared with the samples stored in the image data, before
+/// any scaling to 8 bits. A tRNS chunk of the wrong length is ignored
+fn color_key(png_file: &PngImage) -> Option<Vec<u16>> {
+    let channels = match png_file.color_type {
+        ColorType::Grayscale | ColorType::Truecolor => png_file.channels(),
+        _ => return None,
+    };
+    match png_file.transparency() {
+        Some(trns) if trns.len() == channels * 2 => Some(
+            trns.chunks(2)
+                .map(|sample| u16::from_be_bytes([sample[0], sample[1]]))
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Return the 16-bit sample starting at the beginning of bytes, which is
+/// big-endian unless the options say otherwise
+fn read_sample_16(bytes: &[u8], options: &DecodeOptions) -> u16 {
+    match options.assume_le_16bit {
+        true => u16::from_le_bytes([bytes[0], bytes[1]]),
+        false => u16::from_be_bytes([bytes[0], bytes[1]]),
+    }
+}
+
+/// Decode a single reconstructed scanline of a parsed png image using one of
+/// the decoder functions. The fields of a PngImage can be changed after
+/// parsing, so the bit depth is checked against the color type again
+fn decode_scanline(
+    png_image: &PngImage,
+    scanline: &[u8],
+    options: &DecodeOptions,
+) -> Result<Vec<Color>> {
+    if !allowed_bit_depths(&png_image.color_type).contains(&png_image.bit_depth) {
+        return Err(PngError::NotSupported(format!(
+            "Decoding {} with bit depth {}",
+            png_image.color_type, png_image.bit_depth
+        )));
+    }
+
+    match png_image.color_type {
+        ColorType::Truecolor | ColorType::TrueColorWithAlpha => {
+            Ok(truecolor_scanline_to_pixels(png_image, scanline, options))
         }
-        println!();
+        ColorType::IndexedColor => indexed_color_scanline_to_pixels(png_image, scanline, options),
+        ColorType::Grayscale => Ok(grayscale_scanline_to_pixels(png_image, scanline, options)),
+        ColorType::GrayScaleWithAlpha => Ok(grayscale_alpha_scanline_to_pixels(
+            png_image, scanline, options,
+        )),
     }
 }
 
-/// Decode pixels of a parsed png image assumed to follow a indexed color
-/// format, return Vec<Vec<Color>>, the vectors represent the rows and columns
-/// respectively
-fn png_indexed_color_to_pixels(png_file: &PngImage) -> Vec<Vec<Color>> {
-    let mut res: Vec<Vec<Color>> = Vec::new();
+/// Decode the pixels of a scanline of a parsed png image assumed to follow a
+/// indexed color format
+fn indexed_color_scanline_to_pixels(
+    png_file: &PngImage,
+    scanline: &[u8],
+    options: &DecodeOptions,
+) -> Result<Vec<Color>> {
+    let mut res: Vec<Color> = Vec::new();
     let bits_per_scanline = (png_file.width * png_file.bit_depth as u32) as usize;
 
-    let bytes_per_scanline = (bits_per_scanline as f32 / 8.0).ceil() as usize;
-    let palette = png_file.palette.as_ref().unwrap();
+    let palette = png_file
+        .palette
+        .as_ref()
+        .ok_or_else(|| PngError::WrongFormat("Indexed image has no palette".to_string()))?;
     let mask = ((1_u16 << png_file.bit_depth) - 1) as u8;
+    let mut bits_parsed = 0;
 
-    // Each scanline
-    for scanline_idx in (0..png_file.data.len()).step_by(bytes_per_scanline) {
-        let mut scanline: Vec<Color> = Vec::new();
-        let mut bits_parsed = 0;
-
-        // Iterate over each byte in the scanline
-        for byte_idx in scanline_idx..scanline_idx + bytes_per_scanline {
-            for bit_idx in (0..8).step_by(png_file.bit_depth as usize).rev() {
-                let palette_idx: usize = (png_file.data[byte_idx] >> bit_idx & mask).into();
-                scanline.push(Color {
-                    ..palette[palette_idx]
-                });
-                bits_parsed += png_file.bit_depth as usize;
-                if bits_parsed == bits_per_scanline {
-                    break;
+    // Iterate over each byte in the scanline
+    for byte in scanline {
+        for bit_idx in (0..8).step_by(png_file.bit_depth as usize).rev() {
+            let palette_idx: usize = (byte >> bit_idx & mask).into();
+            let color = match (palette.get(palette_idx), options.palette_oob) {
+                (Some(color), _) => *color,
+                (None, PaletteOob::Fill(color)) => color,
+                (None, PaletteOob::Error) => {
+                    return Err(PngError::WrongFormat(format!(
+                        "Palette index {} out of range for palette of {} colors",
+                        palette_idx,
+                        palette.len()
+                    )))
                 }
+            };
+            res.push(color);
+            bits_parsed += png_file.bit_depth as usize;
+            if bits_parsed == bits_per_scanline {
+                break;
             }
         }
-        res.push(scanline);
     }
-    res
+    Ok(res)
 }
 
-/// Decode pixels of a parsed png image assumed to follow a grayscale color
-/// format, return Vec<Vec<Color>>, the vectors represent the rows and columns
-/// respectively
-fn png_grayscale_to_pixels(png_file: &PngImage) -> Vec<Vec<Color>> {
-    let mut res: Vec<Vec<Color>> = Vec::new();
+/// Decode the pixels of a scanline of a parsed png image assumed to follow a
+/// grayscale color format. Pixels whose sample matches the gray level of the
+/// tRNS chunk are fully transparent
+fn grayscale_scanline_to_pixels(
+    png_file: &PngImage,
+    scanline: &[u8],
+    options: &DecodeOptions,
+) -> Vec<Color> {
+    let key = color_key(png_file).map(|key| key[0]);
+    let gray = |val: u8, sample: u16| Color {
+        red: val,
+        green: val,
+        blue: val,
+        alpha: if key == Some(sample) { 0 } else { 255 },
+    };
 
+    // 16-bit samples are reduced to their most significant byte
+    if png_file.bit_depth == 16 {
+        return scanline
+            .chunks_exact(2)
+            .map(|bytes| {
+                let sample = read_sample_16(bytes, options);
+                gray((sample >> 8) as u8, sample)
+            })
+            .collect();
+    }
+
+    let mut res: Vec<Color> = Vec::new();
     let bits_per_scanline = (png_file.width * png_file.bit_depth as u32) as usize;
-    let bytes_per_scanline = (bits_per_scanline as f32 / 8.0).ceil() as usize;
 
     let mask = ((1_u16 << png_file.bit_depth) - 1) as u8;
 
@@ -74,66 +1126,565 @@ fn png_grayscale_to_pixels(png_file: &PngImage) -> Vec<Vec<Color>> {
         1 => 255,
         2 => 85,
         4 => 17,
-        8 => 1,
-        _ => 0,
+        _ => 1,
     };
+    let mut bits_parsed = 0;
 
-    // Each scanline
-    for scanline_idx in (0..png_file.data.len()).step_by(bytes_per_scanline) {
-        let mut scanline: Vec<Color> = Vec::new();
-        let mut bits_parsed = 0;
-
-        // Iterate over each byte in the scanline
-        for byte_idx in scanline_idx..scanline_idx + bytes_per_scanline {
-            for bit_idx in (0..8).step_by(png_file.bit_depth as usize).rev() {
-                let val: u8 = png_file.data[byte_idx] >> bit_idx & mask;
-                scanline.push(Color {
-                    red: val * scale_factor,
-                    green: val * scale_factor,
-                    blue: val * scale_factor,
-                });
-                bits_parsed += png_file.bit_depth as usize;
-                if bits_parsed == bits_per_scanline {
-                    break;
-                }
+    // Iterate over each byte in the scanline
+    for byte in scanline {
+        for bit_idx in (0..8).step_by(png_file.bit_depth as usize).rev() {
+            let val: u8 = byte >> bit_idx & mask;
+            res.push(gray(val * scale_factor, val as u16));
+            bits_parsed += png_file.bit_depth as usize;
+            if bits_parsed == bits_per_scanline {
+                break;
             }
         }
-        res.push(scanline);
     }
     res
 }
 
-/// Decode pixels of a parsed png image assumed to follow a truecolor png
-/// format, return Vec<Vec<Color>>, the vectors represent the rows and columns
-/// respectively. Note that the alpha channel is ignored for Truecolor images
-/// with alpha
-fn png_truecolor_to_pixels(png_file: &PngImage) -> Vec<Vec<Color>> {
-    let mut res: Vec<Vec<Color>> = Vec::new();
+/// Decode the pixels of a scanline of a parsed png image assumed to follow the
+/// grayscale with alpha format, where every pixel is a gray and an alpha
+/// sample of 8 or 16 bits. The gray sample is used for red, green and blue
+fn grayscale_alpha_scanline_to_pixels(
+    png_file: &PngImage,
+    scanline: &[u8],
+    options: &DecodeOptions,
+) -> Vec<Color> {
     let bytes_per_channel = png_file.bit_depth as usize / 8;
-    let bytes_per_pixel: usize = match png_file.color_type {
-        ColorType::Truecolor => 3,
-        ColorType::TrueColorWithAlpha => 4,
-        _ => panic!(),
+    let msb_offset = sample_msb_offset(png_file, options);
+
+    scanline[msb_offset..]
+        .chunks(png_file.bytes_per_pixel())
+        .map(|pixel| Color {
+            red: pixel[0],
+            green: pixel[0],
+            blue: pixel[0],
+            alpha: pixel[bytes_per_channel],
+        })
+        .collect()
+}
+
+/// Decode the pixels of a scanline of a parsed png image assumed to follow a
+/// truecolor png format. Pixels of Truecolor images without alpha are opaque,
+/// unless their samples match the color of the tRNS chunk which makes them
+/// fully transparent
+fn truecolor_scanline_to_pixels(
+    png_file: &PngImage,
+    scanline: &[u8],
+    options: &DecodeOptions,
+) -> Vec<Color> {
+    let bytes_per_channel = png_file.bit_depth as usize / 8;
+    let key = color_key(png_file);
+
+    // 16-bit samples are reduced to their most significant byte
+    let sample = |bytes: &[u8]| match bytes_per_channel {
+        2 => read_sample_16(bytes, options),
+        _ => bytes[0] as u16,
+    };
+    let to_8bit = |sample: u16| match bytes_per_channel {
+        2 => (sample >> 8) as u8,
+        _ => sample as u8,
     };
-    let bytes_per_scanline = bytes_per_pixel * png_file.width as usize * bytes_per_channel;
 
-    for scanline_idx in 0..png_file.height as usize {
-        let mut scanline: Vec<Color> = Vec::new();
+    scanline
+        .chunks_exact(png_file.bytes_per_pixel())
+        .map(|pixel| {
+            let samples: Vec<u16> = pixel.chunks_exact(bytes_per_channel).map(sample).collect();
+            let alpha = match (samples.get(3), &key) {
+                (Some(&alpha), _) => to_8bit(alpha),
+                (None, Some(key)) if samples == *key => 0,
+                (None, _) => 255,
+            };
+            Color {
+                red: to_8bit(samples[0]),
+                green: to_8bit(samples[1]),
+                blue: to_8bit(samples[2]),
+                alpha,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    /// Check that pixel agrees with decode_png for every pixel of the image
+    fn assert_pixels_match_decode(bytes: &[u8]) {
+        let png_image = parse_png_bytes(bytes).unwrap();
+        let decoded = decode_png(&png_image).unwrap();
+        for (y, row) in decoded.iter().enumerate() {
+            for (x, color) in row.iter().enumerate() {
+                assert_eq!(png_image.pixel(x as u32, y as u32).unwrap(), *color);
+            }
+        }
+    }
+
+    #[test]
+    fn pixel_matches_decode_for_rgb8() {
+        let filtered = [0, 1, 2, 3, 4, 5, 6, 0, 7, 8, 9, 10, 11, 12];
+        assert_pixels_match_decode(&test_util::png(2, 2, 8, 2, &filtered, &[]));
+    }
+
+    #[test]
+    fn pixel_matches_decode_for_rgba16() {
+        let filtered = [
+            0, 0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0, 0xFF, 0xEE, 0xDD, 0xCC, 0xBB, 0xAA,
+            0x99, 0x88,
+        ];
+        assert_pixels_match_decode(&test_util::png(2, 1, 16, 6, &filtered, &[]));
+    }
+
+    #[test]
+    fn decode_streaming_calls_on_row_once_per_row() {
+        let filtered = [0, 1, 2, 3, 0, 4, 5, 6, 0, 7, 8, 9];
+        let bytes = test_util::png(1, 3, 8, 2, &filtered, &[]);
+
+        let mut rows: Vec<u32> = Vec::new();
+        decode_streaming(&bytes, |row_idx, _| rows.push(row_idx)).unwrap();
+        assert_eq!(rows, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn decode_streaming_fails_on_truncated_data() {
+        // The second scanline is cut off after its first two samples
+        let bytes = test_util::png(2, 2, 8, 2, &[0, 1, 2, 3, 4, 5, 6, 0, 7, 8], &[]);
+
+        let res = decode_streaming(&bytes, |_, _| {});
+        assert!(matches!(res, Err(PngError::TruncatedData)));
+        let mut target = DecodedImage {
+            width: 0,
+            height: 0,
+            pixels: Vec::new(),
+        };
+        let res = decode_into_image(&bytes, &mut target);
+        assert!(matches!(res, Err(PngError::TruncatedData)));
+    }
+
+    #[test]
+    fn grayscale_color_key_is_transparent() {
+        let trns = test_util::chunk(b"tRNS", &[0, 2]);
+        let bytes = test_util::png(3, 1, 2, 0, &[0, 0b0110_0000], &[trns]);
+        let alphas: Vec<u8> = decode_png(&parse_png_bytes(&bytes).unwrap()).unwrap()[0]
+            .iter()
+            .map(|pixel| pixel.alpha)
+            .collect();
+        assert_eq!(alphas, vec![255, 0, 255]);
+
+        // 16-bit samples are compared in full, not by their reduced value
+        let trns = test_util::chunk(b"tRNS", &[0x12, 0x34]);
+        let bytes = test_util::png(2, 1, 16, 0, &[0, 0x12, 0x34, 0x12, 0x35], &[trns]);
+        let alphas: Vec<u8> = decode_png(&parse_png_bytes(&bytes).unwrap()).unwrap()[0]
+            .iter()
+            .map(|pixel| pixel.alpha)
+            .collect();
+        assert_eq!(alphas, vec![0, 255]);
+    }
+
+    #[test]
+    fn decode_png_flat_fills_alpha_from_color_key() {
+        let trns = test_util::chunk(b"tRNS", &[0, 18]);
+        let filtered = [0, 0, 18, 0, 19];
+        let bytes = test_util::png(2, 1, 16, 0, &filtered, &[trns]);
+
+        let (data, width, height, channels) =
+            decode_png_flat(&parse_png_bytes(&bytes).unwrap()).unwrap();
+        assert_eq!((width, height, channels), (2, 1, 2));
+        assert_eq!(data, vec![0, 0, 0, 255]);
+
+        // A tRNS chunk of the wrong length does not add an alpha channel
+        let trns = test_util::chunk(b"tRNS", &[0, 0, 0]);
+        let bytes = test_util::png(2, 1, 16, 0, &filtered, &[trns]);
+        let (data, _, _, channels) = decode_png_flat(&parse_png_bytes(&bytes).unwrap()).unwrap();
+        assert_eq!((data, channels), (vec![0, 0], 1));
+    }
+
+    #[test]
+    fn pixel_matches_decode_for_packed_gray() {
+        assert_pixels_match_decode(&test_util::png(
+            5,
+            1,
+            2,
+            0,
+            &[0, 0b0001_1011, 0b1000_0000],
+            &[],
+        ));
+    }
+
+    #[test]
+    fn composite_over_blends_half_transparent_red_over_white() {
+        let red = Color {
+            red: 255,
+            green: 0,
+            blue: 0,
+            alpha: 128,
+        };
+        let white = Color {
+            red: 255,
+            green: 255,
+            blue: 255,
+            alpha: 255,
+        };
+        let pink = Color {
+            red: 255,
+            green: 127,
+            blue: 127,
+            alpha: 255,
+        };
+
+        assert_eq!(
+            composite_over(&[vec![red, white]], white),
+            vec![vec![pink, white]]
+        );
+    }
+
+    #[test]
+    fn decode_all_decodes_concatenated_pngs() {
+        let mut bytes = test_util::png(1, 1, 8, 0, &[0, 10], &[]);
+        bytes.extend(test_util::png(2, 1, 8, 0, &[0, 20, 30], &[]));
+        bytes.extend(test_util::png(1, 2, 8, 0, &[0, 40, 0, 50], &[]));
+        bytes.extend(b"\n\0 ");
+
+        let images = decode_all(&bytes).unwrap();
+        let sizes: Vec<(u32, u32)> = images.iter().map(|img| (img.width, img.height)).collect();
+        assert_eq!(sizes, vec![(1, 1), (2, 1), (1, 2)]);
+        assert_eq!(images[2].pixels[1].red, 50);
+    }
+
+    #[test]
+    fn assume_le_16bit_decodes_byte_swapped_gradient() {
+        let filtered = [0, 0x00, 0x00, 0x00, 0x40, 0x00, 0x80, 0xFF, 0xFF];
+        let png_image = parse_png_bytes(&test_util::png(4, 1, 16, 0, &filtered, &[])).unwrap();
+        let options = DecodeOptions {
+            assume_le_16bit: true,
+            ..Default::default()
+        };
+
+        let decoded = decode_png_with_options(&png_image, &options).unwrap();
+        let grays: Vec<u8> = decoded[0].iter().map(|pixel| pixel.red).collect();
+        assert_eq!(grays, vec![0, 64, 128, 255]);
+    }
+
+    #[test]
+    fn index_at_crosses_byte_boundaries() {
+        let plte = test_util::chunk(b"PLTE", &[0, 0, 0, 255, 255, 255]);
+        let filtered = [0, 0b1010_1100, 0b1100_0000, 0, 0b0000_0001, 0b0100_0000];
+        let bytes = test_util::png(10, 2, 1, 3, &filtered, &[plte]);
+        let (palette, indices) = decode_indexed_packed(&parse_png_bytes(&bytes).unwrap()).unwrap();
+
+        assert_eq!(palette.len(), 2);
+        let rows: Vec<Vec<u8>> = (0..2)
+            .map(|y| (0..10).map(|x| indices.index_at(x, y)).collect())
+            .collect();
+        assert_eq!(rows[0], vec![1, 0, 1, 0, 1, 1, 0, 0, 1, 1]);
+        assert_eq!(rows[1], vec![0, 0, 0, 0, 0, 0, 0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn render_png_styled_returns_one_row_per_scanline() {
+        let filtered = [0, 1, 2, 3, 0, 4, 5, 6, 0, 7, 8, 9];
+        let png_image = parse_png_bytes(&test_util::png(1, 3, 8, 2, &filtered, &[])).unwrap();
 
-        for pixel_idx in (0..bytes_per_scanline).step_by(bytes_per_channel * bytes_per_pixel) {
-            let pixel_start = scanline_idx * bytes_per_scanline + pixel_idx;
-            let red_idx = pixel_start;
-            let green_idx = pixel_start + bytes_per_channel;
-            let blue_idx = pixel_start + bytes_per_channel * 2;
+        let rows = render_png_styled(&decode_png(&png_image).unwrap());
+        assert_eq!(rows.len(), png_image.height as usize);
+    }
+
+    #[test]
+    fn bgra_is_rgba_with_red_and_blue_swapped() {
+        let filtered = [0, 1, 2, 3, 4, 5, 6, 7, 8];
+        let png_image = parse_png_bytes(&test_util::png(2, 1, 8, 6, &filtered, &[])).unwrap();
+
+        let (_, _, rgba) = decode_flat_ordered(&png_image, ChannelOrder::Rgba).unwrap();
+        let (_, _, bgra) = decode_flat_ordered(&png_image, ChannelOrder::Bgra).unwrap();
+        let swapped: Vec<u8> = rgba
+            .chunks(4)
+            .flat_map(|pixel| [pixel[2], pixel[1], pixel[0], pixel[3]])
+            .collect();
+        assert_eq!(bgra, swapped);
+    }
+
+    #[test]
+    fn normalized_f32_maps_extremes_exactly() {
+        let rgba8 = test_util::png(1, 1, 8, 6, &[0, 255, 0, 255, 0], &[]);
+        let (_, _, pixels) = decode_normalized_f32(&parse_png_bytes(&rgba8).unwrap()).unwrap();
+        assert_eq!(pixels, vec![[1.0, 0.0, 1.0, 0.0]]);
+
+        let gray16 = test_util::png(2, 1, 16, 0, &[0, 0xFF, 0xFF, 0, 0], &[]);
+        let (_, _, pixels) = decode_normalized_f32(&parse_png_bytes(&gray16).unwrap()).unwrap();
+        assert_eq!(pixels, vec![[1.0, 1.0, 1.0, 1.0], [0.0, 0.0, 0.0, 1.0]]);
+    }
+
+    #[test]
+    fn palette_oob_errors_or_fills() {
+        let plte = test_util::chunk(b"PLTE", &[10, 20, 30]);
+        let png_image = parse_png_bytes(&test_util::png(2, 1, 8, 3, &[0, 0, 5], &[plte])).unwrap();
+        assert!(decode_png(&png_image).is_err());
+
+        let magenta = Color {
+            red: 255,
+            green: 0,
+            blue: 255,
+            alpha: 255,
+        };
+        let options = DecodeOptions {
+            palette_oob: PaletteOob::Fill(magenta),
+            ..Default::default()
+        };
+        let decoded = decode_png_with_options(&png_image, &options).unwrap();
+        assert_eq!(decoded[0][0].red, 10);
+        assert_eq!(decoded[0][1], magenta);
+    }
 
-            // Decode the RGB value
-            let red: u8 = png_file.data[red_idx];
-            let green: u8 = png_file.data[green_idx];
-            let blue: u8 = png_file.data[blue_idx];
+    #[test]
+    fn ragged_pixel_grid_is_rejected() {
+        let pixel = Color {
+            red: 0,
+            green: 0,
+            blue: 0,
+            alpha: 255,
+        };
+        assert!(matches!(
+            PixelGrid::new(vec![vec![pixel; 2], vec![pixel]]),
+            Err(PngError::WrongFormat(_))
+        ));
 
-            scanline.push(Color { red, green, blue });
+        let grid = PixelGrid::new(vec![vec![pixel; 2]; 3]).unwrap();
+        assert_eq!((grid.width(), grid.height()), (2, 3));
+        assert!(grid.row(3).is_none());
+    }
+
+    #[test]
+    fn red_channel_matches_full_decode() {
+        let files = [
+            test_util::png(
+                2,
+                2,
+                8,
+                2,
+                &[0, 1, 2, 3, 4, 5, 6, 0, 7, 8, 9, 10, 11, 12],
+                &[],
+            ),
+            test_util::png(
+                2,
+                1,
+                16,
+                6,
+                &[0, 9, 1, 8, 2, 7, 3, 6, 4, 5, 5, 4, 6, 3, 7, 2, 8],
+                &[],
+            ),
+        ];
+
+        for bytes in files {
+            let png_image = parse_png_bytes(&bytes).unwrap();
+            let red: Vec<Vec<u8>> = decode_png(&png_image)
+                .unwrap()
+                .iter()
+                .map(|row| row.iter().map(|pixel| pixel.red).collect())
+                .collect();
+            assert_eq!(decode_channel(&png_image, Channel::Red).unwrap(), red);
         }
-        res.push(scanline);
     }
-    res
+
+    #[test]
+    fn wrongly_sized_decode_is_an_error() {
+        let filtered = [0, 1, 2, 3, 4, 5, 6, 0, 7, 8, 9, 10, 11, 12];
+        let mut png_image = parse_png_bytes(&test_util::png(2, 2, 8, 2, &filtered, &[])).unwrap();
+        png_image.data.truncate(6);
+        assert!(matches!(
+            decode_png(&png_image),
+            Err(PngError::WrongFormat(_))
+        ));
+
+        let pixel = Color {
+            red: 0,
+            green: 0,
+            blue: 0,
+            alpha: 255,
+        };
+        let ragged = vec![vec![pixel; 2], vec![pixel; 1]];
+        assert!(check_dimensions(&png_image, &ragged).is_err());
+        assert!(check_dimensions(&png_image, &[vec![pixel; 2], vec![pixel; 2]]).is_ok());
+    }
+
+    #[test]
+    fn grayscale_single_returns_one_byte_per_pixel() {
+        let filtered = [0, 10, 20, 30, 0, 40, 50, 60];
+        let png_image = parse_png_bytes(&test_util::png(3, 2, 8, 0, &filtered, &[])).unwrap();
+
+        let gray = decode_grayscale_single(&png_image).unwrap();
+        assert_eq!(gray.iter().flatten().count(), 6);
+        assert_eq!(gray, vec![vec![10, 20, 30], vec![40, 50, 60]]);
+
+        let rgb = parse_png_bytes(&test_util::png(1, 1, 8, 2, &[0, 1, 2, 3], &[])).unwrap();
+        assert!(decode_grayscale_single(&rgb).is_err());
+    }
+
+    #[test]
+    fn decode_into_image_reuses_allocation() {
+        let large = test_util::png(4, 4, 8, 0, &[0; 20], &[]);
+        let small = test_util::png(2, 1, 8, 0, &[0, 7, 9], &[]);
+
+        let mut target = DecodedImage {
+            width: 0,
+            height: 0,
+            pixels: Vec::new(),
+        };
+        decode_into_image(&large, &mut target).unwrap();
+        let capacity = target.pixels.capacity();
+        assert!(capacity >= 16);
+
+        decode_into_image(&small, &mut target).unwrap();
+        assert_eq!((target.width, target.height), (2, 1));
+        assert_eq!(target.pixels.len(), 2);
+        assert_eq!(target.pixels[1].red, 9);
+        assert_eq!(target.pixels.capacity(), capacity);
+    }
+
+    #[test]
+    fn content_hash_tells_images_apart() {
+        let decode = |filtered: &[u8]| {
+            DecodedImage::from_png(
+                &parse_png_bytes(&test_util::png(2, 1, 8, 2, filtered, &[])).unwrap(),
+            )
+            .unwrap()
+        };
+        let original = decode(&[0, 1, 2, 3, 4, 5, 6]);
+
+        assert_eq!(
+            original.content_hash(),
+            decode(&[0, 1, 2, 3, 4, 5, 6]).content_hash()
+        );
+        assert_ne!(
+            original.content_hash(),
+            decode(&[0, 1, 2, 3, 4, 5, 7]).content_hash()
+        );
+    }
+
+    #[test]
+    fn semi_transparent_alpha_is_preserved() {
+        let rgba = test_util::png(2, 1, 8, 6, &[0, 10, 20, 30, 128, 1, 2, 3, 255], &[]);
+        let decoded = decode_png(&parse_png_bytes(&rgba).unwrap()).unwrap();
+        assert_eq!(decoded[0][0].alpha, 128);
+        assert_eq!(decoded[0][1].alpha, 255);
+
+        let rgb = test_util::png(1, 1, 8, 2, &[0, 10, 20, 30], &[]);
+        let decoded = decode_png(&parse_png_bytes(&rgb).unwrap()).unwrap();
+        assert_eq!(decoded[0][0].alpha, 255);
+    }
+
+    #[test]
+    fn out_of_range_palette_index_is_an_error() {
+        let plte = test_util::chunk(b"PLTE", &[0, 0, 0, 255, 255, 255]);
+        let png_image =
+            parse_png_bytes(&test_util::png(2, 1, 2, 3, &[0, 0b0111_0000], &[plte])).unwrap();
+
+        match decode_png(&png_image) {
+            Err(PngError::WrongFormat(message)) => {
+                assert_eq!(
+                    message,
+                    "Palette index 3 out of range for palette of 2 colors"
+                )
+            }
+            res => panic!("Expected WrongFormat, got {:?}", res),
+        }
+        assert!(png_image.pixel(0, 0).is_ok());
+        assert!(png_image.pixel(1, 0).is_err());
+    }
+
+    #[test]
+    fn apply_gamma_brightens_linear_midtone() {
+        let gama = test_util::chunk(b"gAMA", &100000_u32.to_be_bytes());
+        let png_image = parse_png_bytes(&test_util::png(1, 1, 8, 0, &[0, 128], &[gama])).unwrap();
+        assert_eq!(png_image.gamma(), Some(1.0));
+
+        assert_eq!(decode_png(&png_image).unwrap()[0][0].red, 128);
+        let options = DecodeOptions {
+            apply_gamma: true,
+            ..Default::default()
+        };
+        let corrected = decode_png_with_options(&png_image, &options).unwrap();
+        assert_eq!(corrected[0][0].red, 186);
+        assert_eq!(corrected[0][0].alpha, 255);
+    }
+
+    #[test]
+    fn pure_red_grayscale_uses_luminance_weights() {
+        let red = Color {
+            red: 255,
+            green: 0,
+            blue: 0,
+            alpha: 100,
+        };
+
+        let gray = to_grayscale(&[vec![red]]);
+        assert_eq!(
+            gray[0][0],
+            Color {
+                red: 76,
+                green: 76,
+                blue: 76,
+                alpha: 100,
+            }
+        );
+    }
+
+    /// Compares the scanline decoding of decode_png, which runs on the rayon
+    /// thread pool when the feature is on, with the sequential ScanlineIter
+    #[test]
+    fn decode_png_matches_sequential_decoding() {
+        let (width, height) = (37_usize, 64_usize);
+        let filtered: Vec<u8> = (0..height)
+            .flat_map(|y| {
+                let row = (0..width * 4).map(move |x| (x * 7 + y * 13) as u8);
+                std::iter::once((y % 5) as u8).chain(row)
+            })
+            .collect();
+        let bytes = test_util::png(width as u32, height as u32, 8, 6, &filtered, &[]);
+        let png_image = parse_png_bytes(&bytes).unwrap();
+
+        let sequential: Vec<Vec<Color>> = ScanlineIter::new(&png_image)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(decode_png(&png_image).unwrap().into_rows(), sequential);
+
+        // A single bad row fails the whole image in both cases
+        let plte = test_util::chunk(b"PLTE", &[0; 30]);
+        let mut indices = vec![0_u8; 65 * 64];
+        indices[40 * 65 + 17] = 10;
+        let indexed = parse_png_bytes(&test_util::png(64, 64, 8, 3, &indices, &[plte])).unwrap();
+        let sequential_error = ScanlineIter::new(&indexed)
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap_err();
+        assert_eq!(
+            format!("{:?}", decode_png(&indexed).unwrap_err()),
+            format!("{:?}", sequential_error)
+        );
+    }
+
+    #[test]
+    fn decode_channel_of_indexed_image_uses_palette() {
+        let plte = test_util::chunk(b"PLTE", &[10, 20, 30, 40, 50, 60]);
+        let png_image = parse_png_bytes(&test_util::png(2, 1, 8, 3, &[0, 1, 0], &[plte])).unwrap();
+        assert_eq!(
+            decode_channel(&png_image, Channel::Red).unwrap(),
+            vec![vec![40, 10]]
+        );
+        assert_eq!(
+            decode_channel(&png_image, Channel::Blue).unwrap(),
+            vec![vec![60, 30]]
+        );
+        assert_eq!(
+            decode_channel(&png_image, Channel::Alpha).unwrap(),
+            vec![vec![255, 255]]
+        );
+        assert!(matches!(
+            decode_channel(&png_image, Channel::Gray),
+            Err(PngError::NotSupported(_))
+        ));
+    }
 }
@@ -0,0 +1,646 @@
+use crate::parser::{
+    calc_bytes_per_scanline_and_filt_offset, chunk_layout, crc32, Color, ColorType, PngError,
+    PngImage, Result,
+};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::Write;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// Append a chunk to buf, this writes the length, the chunk type, the chunk
+/// data and the crc32 checksum calculated over the type and the data
+fn write_chunk(buf: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let crc_start = buf.len();
+    buf.extend_from_slice(chunk_type);
+    buf.extend_from_slice(data);
+    let crc = crc32(&buf[crc_start..]);
+
+    buf.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Write the png signature followed by an IHDR chunk with the given metadata.
+/// Compression and filter method are always 0
+fn write_header(
+    buf: &mut Vec<u8>,
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: u8,
+    interlace_method: u8,
+) {
+    buf.extend_from_slice(&PNG_SIGNATURE);
+
+    let mut ihdr: Vec<u8> = Vec::new();
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    ihdr.extend_from_slice(&[bit_depth, color_type, 0, 0, interlace_method]);
+
+    write_chunk(buf, b"IHDR", &ihdr);
+}
+
+/// Prefix every scanline with filter type 0 (None) and compress the result
+/// into a single zlib stream suitable for an IDAT chunk
+fn compress_scanlines(scanlines: &[Vec<u8>]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+
+    for scanline in scanlines {
+        encoder
+            .write_all(&[0])
+            .and_then(|_| encoder.write_all(scanline))
+            .map_err(|_| PngError::CompressionFailed)?;
+    }
+
+    encoder.finish().map_err(|_| PngError::CompressionFailed)
+}
+
+/// Write the IDAT and IEND chunks that finish off an image
+fn write_image_data(buf: &mut Vec<u8>, scanlines: &[Vec<u8>]) -> Result<()> {
+    let compressed = compress_scanlines(scanlines)?;
+    write_chunk(buf, b"IDAT", &compressed);
+    write_chunk(buf, b"IEND", &[]);
+    Ok(())
+}
+
+/// Return the width and height of a two-dimensional vector of rows, returns an
+/// error if it is empty or if the rows are of different lengths
+fn grid_dimensions<T>(rows: &[Vec<T>]) -> Result<(u32, u32)> {
+    let width = rows.first().map_or(0, |row| row.len());
+
+    if width == 0 {
+        return Err(PngError::WrongFormat(
+            "Cannot encode an empty image".to_string(),
+        ));
+    }
+    if rows.iter().any(|row| row.len() != width) {
+        return Err(PngError::WrongFormat(
+            "All rows must have the same length".to_string(),
+        ));
+    }
+    Ok((width as u32, rows.len() as u32))
+}
+
+/// Return the smallest bit depth able to index a palette with len entries, or
+/// None if the palette is too large for an indexed color png
+pub fn min_bit_depth_for_palette(len: usize) -> Option<u8> {
+    match len {
+        0..=2 => Some(1),
+        3..=4 => Some(2),
+        5..=16 => Some(4),
+        17..=256 => Some(8),
+        _ => None,
+    }
+}
+
+/// Pack a row of samples that each fit in bit_depth bits into bytes, the first
+/// sample ends up in the most significant bits of the first byte
+fn pack_scanline(samples: &[u8], bit_depth: u8) -> Vec<u8> {
+    let samples_per_byte = (8 / bit_depth) as usize;
+
+    samples
+        .chunks(samples_per_byte)
+        .map(|chunk| {
+            chunk.iter().enumerate().fold(0, |byte, (idx, &sample)| {
+                byte | sample << (8 - bit_depth as usize * (idx + 1))
+            })
+        })
+        .collect()
+}
+
+/// Write a PLTE chunk with the colors of the palette. If any entry is not
+/// fully opaque a tRNS chunk is written containing the alpha values of the
+/// palette up to the last non-opaque entry
+fn write_palette(buf: &mut Vec<u8>, palette: &[Color]) {
+    let plte: Vec<u8> = palette
+        .iter()
+        .flat_map(|color| [color.red, color.green, color.blue])
+        .collect();
+    write_chunk(buf, b"PLTE", &plte);
+
+    // Trailing opaque entries may be left out of tRNS, they default to 255
+    if let Some(last) = palette.iter().rposition(|color| color.alpha != 255) {
+        let trns: Vec<u8> = palette[..=last].iter().map(|color| color.alpha).collect();
+        write_chunk(buf, b"tRNS", &trns);
+    }
+}
+
+/// Encode an image given as palette indices into an indexed color png, using
+/// the smallest bit depth that fits the palette
+pub fn encode_indexed(indices: &[Vec<u8>], palette: &[Color]) -> Result<Vec<u8>> {
+    let (width, height) = grid_dimensions(indices)?;
+
+    let bit_depth = match min_bit_depth_for_palette(palette.len()) {
+        Some(depth) if !palette.is_empty() => depth,
+        _ => {
+            return Err(PngError::WrongFormat(
+                "Palette must contain between 1 and 256 entries".to_string(),
+            ))
+        }
+    };
+    if indices
+        .iter()
+        .flatten()
+        .any(|&idx| idx as usize >= palette.len())
+    {
+        return Err(PngError::WrongFormat(
+            "Palette index out of range".to_string(),
+        ));
+    }
+
+    let mut buf: Vec<u8> = Vec::new();
+    write_header(&mut buf, width, height, bit_depth, 3, 0);
+    write_palette(&mut buf, palette);
+
+    let scanlines: Vec<Vec<u8>> = indices
+        .iter()
+        .map(|row| pack_scanline(row, bit_depth))
+        .collect();
+
+    write_image_data(&mut buf, &scanlines)?;
+    Ok(buf)
+}
+
+/// Encode pixels into a truecolor png with bit depth 8. The alpha channel of
+/// the pixels is not written, if transparent is given a tRNS chunk is written
+/// marking all pixels of that RGB value as fully transparent
+pub fn encode_truecolor(pixels: &[Vec<Color>], transparent: Option<&Color>) -> Result<Vec<u8>> {
+    let (width, height) = grid_dimensions(pixels)?;
+
+    let mut buf: Vec<u8> = Vec::new();
+    write_header(&mut buf, width, height, 8, 2, 0);
+
+    // tRNS for truecolor images holds a single RGB value as three 16-bit samples
+    if let Some(color) = transparent {
+        let trns: Vec<u8> = [color.red, color.green, color.blue]
+            .iter()
+            .flat_map(|&sample| (sample as u16).to_be_bytes())
+            .collect();
+        write_chunk(&mut buf, b"tRNS", &trns);
+    }
+
+    let scanlines: Vec<Vec<u8>> = pixels
+        .iter()
+        .map(|row| {
+            row.iter()
+                .flat_map(|pixel| [pixel.red, pixel.green, pixel.blue])
+                .collect()
+        })
+        .collect();
+
+    write_image_data(&mut buf, &scanlines)?;
+    Ok(buf)
+}
+
+/// Encode pixels into a png of the given color type and bit depth. Only bit
+/// depth 8 and the color types without a palette are supported, use
+/// encode_indexed for indexed color. Grayscale images take the red channel of
+/// every pixel as the gray value and the alpha channel is only written for
+/// color types that have one
+pub fn encode_png(pixels: &[Vec<Color>], color_type: ColorType, bit_depth: u8) -> Result<Vec<u8>> {
+    let (width, height) = grid_dimensions(pixels)?;
+
+    if bit_depth != 8 {
+        return Err(PngError::NotSupported(format!(
+            "Encoding with bit depth {}",
+            bit_depth
+        )));
+    }
+    let color_type_id = match color_type {
+        ColorType::Grayscale => 0,
+        ColorType::Truecolor => 2,
+        ColorType::GrayScaleWithAlpha => 4,
+        ColorType::TrueColorWithAlpha => 6,
+        ColorType::IndexedColor => {
+            return Err(PngError::NotSupported(
+                "Encoding IndexedColor, use encode_indexed".to_string(),
+            ))
+        }
+    };
+
+    let mut buf: Vec<u8> = Vec::new();
+    write_header(&mut buf, width, height, bit_depth, color_type_id, 0);
+
+    let scanlines: Vec<Vec<u8>> = pixels
+        .iter()
+        .map(|row| {
+            row.iter()
+                .flat_map(|pixel| {
+                    let samples = [pixel.red, pixel.green, pixel.blue, pixel.alpha];
+                    match color_type {
+                        ColorType::Grayscale => vec![pixel.red],
+                        ColorType::GrayScaleWithAlpha => vec![pixel.red, pixel.alpha],
+                        ColorType::Truecolor => samples[..3].to_vec(),
+                        _ => samples.to_vec(),
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    write_image_data(&mut buf, &scanlines)?;
+    Ok(buf)
+}
+
+/// Encode pixels with encode_png and write the result to a file at path. The
+/// image is saved as 8-bit truecolor, with an alpha channel only if some pixel
+/// is not fully opaque
+pub fn save_png(path: &str, pixels: &[Vec<Color>]) -> Result<()> {
+    let color_type = if pixels.iter().flatten().any(|pixel| pixel.alpha != 255) {
+        ColorType::TrueColorWithAlpha
+    } else {
+        ColorType::Truecolor
+    };
+    let encoded = encode_png(pixels, color_type, 8)?;
+
+    std::fs::write(path, encoded).map_err(|_| PngError::CouldNotWriteFile)
+}
+
+/// Write pixels to a file at path as a binary PPM (P6) image, a header giving
+/// the dimensions followed by the red, green and blue bytes of every pixel.
+/// Alpha is dropped, returns an error if the rows are of different lengths
+pub fn write_ppm(path: &str, pixels: &[Vec<Color>]) -> Result<()> {
+    let (width, height) = grid_dimensions(pixels)?;
+
+    let mut buf: Vec<u8> = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    for pixel in pixels.iter().flatten() {
+        buf.extend_from_slice(&[pixel.red, pixel.green, pixel.blue]);
+    }
+
+    std::fs::write(path, buf).map_err(|_| PngError::CouldNotWriteFile)
+}
+
+/// Write the signature, the IHDR chunk and the PLTE or tRNS chunk of a parsed
+/// png image with its own color type and bit depth
+fn write_matching_header(
+    buf: &mut Vec<u8>,
+    png_image: &PngImage,
+    interlace_method: u8,
+) -> Result<()> {
+    let color_type = match png_image.color_type {
+        ColorType::Grayscale => 0,
+        ColorType::Truecolor => 2,
+        ColorType::IndexedColor => 3,
+        ColorType::GrayScaleWithAlpha => 4,
+        ColorType::TrueColorWithAlpha => 6,
+    };
+    write_header(
+        buf,
+        png_image.width,
+        png_image.height,
+        png_image.bit_depth,
+        color_type,
+        interlace_method,
+    );
+
+    match (&png_image.color_type, &png_image.palette) {
+        (ColorType::IndexedColor, Some(palette)) => write_palette(buf, palette),
+        (ColorType::IndexedColor, None) => {
+            return Err(PngError::WrongFormat(
+                "Indexed color image has no palette".to_string(),
+            ))
+        }
+        _ => {
+            if let Some(transparency) = png_image.transparency() {
+                write_chunk(buf, b"tRNS", transparency);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Encode a parsed png image keeping its color type, bit depth and palette, so
+/// that parsing the result gives back the same data. The reconstructed
+/// scanlines in data are always compressed again, so changes made to data
+/// end up in the result. Interlaced images are written deinterlaced
+pub fn encode_matching(png_image: &PngImage) -> Result<Vec<u8>> {
+    let (bytes_per_scanline, _) = calc_bytes_per_scanline_and_filt_offset(
+        png_image.width,
+        png_image.bit_depth,
+        &png_image.color_type,
+    )?;
+    if png_image.width == 0
+        || png_image.height == 0
+        || png_image.data.len() != bytes_per_scanline * png_image.height as usize
+    {
+        return Err(PngError::WrongFormat(
+            "Image data does not match the image dimensions".to_string(),
+        ));
+    }
+
+    let mut buf: Vec<u8> = Vec::new();
+    write_matching_header(&mut buf, png_image, 0)?;
+
+    let scanlines: Vec<Vec<u8>> = png_image
+        .data
+        .chunks(bytes_per_scanline)
+        .map(|scanline| scanline.to_vec())
+        .collect();
+
+    write_image_data(&mut buf, &scanlines)?;
+    Ok(buf)
+}
+
+/// Write a parsed png image out again with the compressed IDAT data kept by
+/// parsing with keep_raw_idat, copied byte for byte instead of compressing the
+/// data again. The header, palette and tRNS chunk are written like
+/// encode_matching does and the interlace method of the file is kept. The
+/// data field is not used, so changes made to it are not part of the result.
+/// Returns an error if the image has no raw IDAT data
+pub fn encode_passthrough(png_image: &PngImage) -> Result<Vec<u8>> {
+    let raw_idat = png_image.raw_idat().ok_or_else(|| {
+        PngError::NotSupported("Passthrough of an image without raw IDAT data".to_string())
+    })?;
+
+    let mut buf: Vec<u8> = Vec::new();
+    write_matching_header(&mut buf, png_image, png_image.is_interlaced() as u8)?;
+    write_chunk(&mut buf, b"IDAT", raw_idat);
+    write_chunk(&mut buf, b"IEND", &[]);
+    Ok(buf)
+}
+
+/// Encode bytes as standard base64 with padding
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut res = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for group in bytes.chunks(3) {
+        // Pack up to three bytes into 24 bits and emit one character per
+        // 6 bits, padding with '=' for missing input bytes
+        let packed = group.iter().enumerate().fold(0_u32, |acc, (idx, &byte)| {
+            acc | (byte as u32) << (16 - 8 * idx)
+        });
+        for idx in 0..4 {
+            if idx <= group.len() {
+                res.push(ALPHABET[(packed >> (18 - 6 * idx) & 0x3F) as usize] as char);
+            } else {
+                res.push('=');
+            }
+        }
+    }
+    res
+}
+
+/// Re-encode a parsed png image with encode_matching and return it as a
+/// base64 data URL that can be embedded directly in HTML
+pub fn to_data_url(png_image: &PngImage) -> Result<String> {
+    let encoded = encode_matching(png_image)?;
+    Ok(format!("data:image/png;base64,{}", base64_encode(&encoded)))
+}
+
+/// Add or replace the sRGB and gAMA chunks of the png file in bytes without
+/// touching anything else. Every other chunk, including IDAT, is copied byte
+/// for byte. A given rendering intent replaces any sRGB chunk and a given
+/// gamma replaces any gAMA chunk, new chunks are placed right after IHDR.
+/// Chunks that are not given are left as they are
+pub fn retag_color(bytes: &[u8], srgb_intent: Option<u8>, gamma: Option<f64>) -> Result<Vec<u8>> {
+    if srgb_intent.is_some_and(|intent| intent > 3) {
+        return Err(PngError::WrongFormat(
+            "Invalid sRGB rendering intent".to_string(),
+        ));
+    }
+    let gamma = match gamma.map(|gamma| (gamma * 100000.0).round()) {
+        Some(value) if !(1.0..=u32::MAX as f64).contains(&value) => {
+            return Err(PngError::WrongFormat("Invalid gamma".to_string()))
+        }
+        value => value.map(|value| value as u32),
+    };
+
+    let mut buf: Vec<u8> = Vec::with_capacity(bytes.len() + 30);
+    buf.extend_from_slice(&PNG_SIGNATURE);
+
+    for (name, span) in chunk_layout(bytes)? {
+        let replaced = match name.as_str() {
+            "gAMA" => gamma.is_some(),
+            "sRGB" => srgb_intent.is_some(),
+            _ => false,
+        };
+        if !replaced {
+            buf.extend_from_slice(&bytes[span]);
+        }
+
+        if name == "IHDR" {
+            if let Some(gamma) = gamma {
+                write_chunk(&mut buf, b"gAMA", &gamma.to_be_bytes());
+            }
+            if let Some(intent) = srgb_intent {
+                write_chunk(&mut buf, b"sRGB", &[intent]);
+            }
+        }
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::decode_png;
+    use crate::parser::{parse_png_bytes, parse_png_bytes_with_options, ParseOptions};
+    use crate::test_util;
+
+    fn color(red: u8, green: u8, blue: u8, alpha: u8) -> Color {
+        Color {
+            red,
+            green,
+            blue,
+            alpha,
+        }
+    }
+
+    #[test]
+    fn indexed_transparency_round_trips() {
+        let palette = [
+            color(255, 0, 0, 255),
+            color(0, 255, 0, 128),
+            color(0, 0, 255, 0),
+        ];
+        let indices = vec![vec![0, 1, 2], vec![2, 1, 0]];
+
+        let encoded = encode_indexed(&indices, &palette).unwrap();
+        let decoded = decode_png(&parse_png_bytes(&encoded).unwrap()).unwrap();
+
+        let expected: Vec<Vec<Color>> = indices
+            .iter()
+            .map(|row| row.iter().map(|&idx| palette[idx as usize]).collect())
+            .collect();
+        assert_eq!(decoded.into_rows(), expected);
+    }
+
+    #[test]
+    fn truecolor_transparency_round_trips() {
+        let key = color(10, 20, 30, 255);
+        let pixels = vec![vec![key, color(10, 20, 31, 255)]];
+
+        let encoded = encode_truecolor(&pixels, Some(&key)).unwrap();
+        let decoded = decode_png(&parse_png_bytes(&encoded).unwrap()).unwrap();
+
+        assert_eq!(
+            decoded.into_rows(),
+            vec![vec![color(10, 20, 30, 0), color(10, 20, 31, 255)]]
+        );
+    }
+
+    #[test]
+    fn min_bit_depth_for_palette_boundaries() {
+        assert_eq!(min_bit_depth_for_palette(2), Some(1));
+        assert_eq!(min_bit_depth_for_palette(4), Some(2));
+        assert_eq!(min_bit_depth_for_palette(16), Some(4));
+        assert_eq!(min_bit_depth_for_palette(256), Some(8));
+        assert_eq!(min_bit_depth_for_palette(257), None);
+    }
+
+    #[test]
+    fn encode_matching_preserves_format_and_pixels() {
+        let plte = test_util::chunk(b"PLTE", &[0, 0, 0, 255, 0, 0, 0, 255, 0]);
+        let files = [
+            test_util::png(5, 1, 2, 3, &[0, 0b0001_1000, 0b0100_0000], &[plte]),
+            test_util::png(2, 1, 16, 0, &[0, 0x12, 0x34, 0xAB, 0xCD], &[]),
+            test_util::png(1, 2, 8, 6, &[0, 1, 2, 3, 4, 0, 5, 6, 7, 8], &[]),
+        ];
+
+        for bytes in files {
+            let original = parse_png_bytes(&bytes).unwrap();
+            let reencoded = parse_png_bytes(&encode_matching(&original).unwrap()).unwrap();
+
+            assert_eq!(reencoded.color_type(), original.color_type());
+            assert_eq!(reencoded.bit_depth, original.bit_depth);
+            assert_eq!(reencoded.data, original.data);
+            assert_eq!(
+                decode_png(&reencoded).unwrap(),
+                decode_png(&original).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn data_url_decodes_back_to_png() {
+        assert_eq!(base64_encode(b"Man"), "TWFu");
+        assert_eq!(base64_encode(b"Ma"), "TWE=");
+        assert_eq!(base64_encode(b"M"), "TQ==");
+
+        let png_image =
+            parse_png_bytes(&test_util::png(2, 1, 8, 2, &[0, 1, 2, 3, 4, 5, 6], &[])).unwrap();
+        let url = to_data_url(&png_image).unwrap();
+        let payload = url.strip_prefix("data:image/png;base64,").unwrap();
+
+        const ALPHABET: &[u8; 64] =
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let sextets: Vec<u8> = payload
+            .bytes()
+            .filter(|&byte| byte != b'=')
+            .map(|byte| ALPHABET.iter().position(|&c| c == byte).unwrap() as u8)
+            .collect();
+        let decoded: Vec<u8> = sextets
+            .chunks(4)
+            .flat_map(|group| {
+                let packed = group.iter().enumerate().fold(0_u32, |acc, (idx, &sextet)| {
+                    acc | (sextet as u32) << (18 - 6 * idx)
+                });
+                (0..group.len() - 1).map(move |idx| (packed >> (16 - 8 * idx)) as u8)
+            })
+            .collect();
+
+        let reparsed = parse_png_bytes(&decoded).unwrap();
+        assert_eq!(reparsed.data, png_image.data);
+    }
+
+    #[test]
+    fn retag_color_adds_srgb_and_keeps_idat() {
+        let bytes = test_util::png(2, 1, 8, 2, &[0, 1, 2, 3, 4, 5, 6], &[]);
+        let tagged = retag_color(&bytes, Some(0), None).unwrap();
+
+        let chunks_of = |buf: &[u8]| -> Vec<(String, Vec<u8>)> {
+            crate::parser::chunk_layout(buf)
+                .unwrap()
+                .into_iter()
+                .map(|(name, span)| (name, buf[span].to_vec()))
+                .collect()
+        };
+        let (before, after) = (chunks_of(&bytes), chunks_of(&tagged));
+        let names: Vec<&str> = after.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["IHDR", "sRGB", "IDAT", "IEND"]);
+
+        let idat = |chunks: &[(String, Vec<u8>)]| {
+            chunks
+                .iter()
+                .find(|(name, _)| name == "IDAT")
+                .unwrap()
+                .1
+                .clone()
+        };
+        assert_eq!(idat(&after), idat(&before));
+        assert_eq!(parse_png_bytes(&tagged).unwrap().srgb_intent(), Some(0));
+    }
+
+    #[test]
+    fn encode_png_round_trips() {
+        let pixels = vec![
+            vec![color(255, 0, 0, 255), color(0, 255, 0, 128)],
+            vec![color(0, 0, 255, 0), color(1, 2, 3, 4)],
+        ];
+
+        let rgba = encode_png(&pixels, ColorType::TrueColorWithAlpha, 8).unwrap();
+        let decoded = decode_png(&parse_png_bytes(&rgba).unwrap()).unwrap();
+        assert_eq!(decoded.into_rows(), pixels);
+
+        let rgb = encode_png(&pixels, ColorType::Truecolor, 8).unwrap();
+        let png_image = parse_png_bytes(&rgb).unwrap();
+        assert_eq!((png_image.width, png_image.height), (2, 2));
+        assert_eq!(decode_png(&png_image).unwrap()[1][1], color(1, 2, 3, 255));
+    }
+
+    #[test]
+    fn passthrough_remux_is_byte_identical() {
+        let plte = test_util::chunk(b"PLTE", &[0, 0, 0, 255, 255, 255]);
+        let mut files = vec![
+            test_util::png(3, 2, 8, 2, &[0; 20], &[]),
+            test_util::png(9, 1, 1, 3, &[0, 0b1010_0101, 0b1000_0000], &[plte]),
+        ];
+        if cfg!(feature = "adam7") {
+            files.push(test_util::file(&[
+                test_util::ihdr(1, 1, 8, 0, 1),
+                test_util::idat(&[0, 42]),
+                test_util::chunk(b"IEND", &[]),
+            ]));
+        }
+        let options = ParseOptions {
+            keep_raw_idat: true,
+            ..Default::default()
+        };
+
+        for bytes in files {
+            let png_image = parse_png_bytes_with_options(&bytes, &options).unwrap();
+            assert_eq!(encode_passthrough(&png_image).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn passthrough_needs_raw_idat() {
+        let png_image = parse_png_bytes(&test_util::png(1, 1, 8, 0, &[0, 0], &[])).unwrap();
+        assert!(matches!(
+            encode_passthrough(&png_image),
+            Err(PngError::NotSupported(_))
+        ));
+    }
+
+    #[test]
+    fn encode_matching_writes_edited_data() {
+        let bytes = test_util::png(2, 1, 8, 2, &[0, 1, 2, 3, 4, 5, 6], &[]);
+        let options = ParseOptions {
+            keep_raw_idat: true,
+            ..Default::default()
+        };
+        let mut png_image = parse_png_bytes_with_options(&bytes, &options).unwrap();
+        assert!(png_image.raw_idat().is_some());
+
+        png_image.data[3..6].copy_from_slice(&[200, 100, 50]);
+        let reparsed = parse_png_bytes(&encode_matching(&png_image).unwrap()).unwrap();
+
+        assert_eq!(reparsed.data, vec![1, 2, 3, 200, 100, 50]);
+        assert_eq!(
+            decode_png(&reparsed).unwrap()[0][1],
+            color(200, 100, 50, 255)
+        );
+    }
+}
@@ -1,5 +1,15 @@
 fn main() {
     let args: Vec<String> = std::env::args().collect();
-    let path = &args[1];
-    rust_png_reader::read_and_print_png(path);
+    let path = match args.get(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: {} <path/to/a/png>", args[0]);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(error) = rust_png_reader::read_and_print_png(path) {
+        eprintln!("{}", error);
+        std::process::exit(error.exit_code());
+    }
 }
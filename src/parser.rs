@@ -1,475 +1,3317 @@
-use std::io::Read;
-
-pub type Result<T> = std::result::Result<T, PngError>;
-
-#[derive(Debug)]
-pub enum PngError {
-    CouldNotReadFile,
-    ChecksumFailure,
-    NotAPng,
-    WrongFormat(String),
-    FilterNotSupported(u8),
-    DecompressionFailed,
-    NotSupported(String),
-}
-
-impl std::error::Error for PngError {}
-
-impl std::fmt::Display for PngError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            PngError::CouldNotReadFile => write!(f, "Could not read the file"),
-            PngError::ChecksumFailure => write!(f, "Checksum incorrect"),
-            PngError::NotAPng => write!(f, "File is not png format"),
-            PngError::WrongFormat(t) => write!(f, "Incorrect png format: {}", t),
-            PngError::FilterNotSupported(t) => write!(f, "Filter type {} not supported", t),
-            PngError::DecompressionFailed => write!(f, "Decompression failed!"),
-            PngError::NotSupported(t) => write!(f, "Not supported: {}", t),
-        }
-    }
-}
-
-#[derive(Debug)]
-pub enum ColorType {
-    Grayscale,
-    Truecolor,
-    IndexedColor,
-    GrayScaleWithAlpha,
-    TrueColorWithAlpha,
-}
-
-#[derive(Debug)]
-enum CompressionMethod {
-    DeflateInflate,
-}
-
-#[derive(Debug)]
-enum FilterMethod {
-    FiveTypeAdaptive,
-}
-
-#[derive(Debug)]
-enum InterlaceMethod {
-    NoInterlace,
-    Adam7Interlace,
-}
-
-#[derive(Debug)]
-enum ChunkType {
-    Ihrd,
-    Plte,
-    Idat,
-    Iend,
-    Ancillary(String),
-}
-
-/// Calculate crc32 checksum for the bytes in seq, pretty much stolen from
-/// here: https://lxp32.github.io/docs/a-simple-example-crc32-calculation/
-fn crc32(seq: &[u8]) -> u32 {
-    let mut crc: u32 = 0xFFFFFFFF;
-
-    for ch in seq.iter() {
-        let mut c = *ch as u32;
-        for _ in 0..8 {
-            let b = (c ^ crc) & 1;
-            crc >>= 1;
-            if b > 0 {
-                crc ^= 0xEDB88320;
-            }
-            c >>= 1;
-        }
-    }
-    !crc
-}
-
-#[derive(Debug)]
-/// struct representing a raw chunk of a png file
-struct Chunk {
-    length: u32,
-    chunk_type: ChunkType,
-    chunk_data: Option<Vec<u8>>,
-}
-
-impl Chunk {
-    /// Construct a Chunk from a buffer and a starting index
-    fn from_buffer_index(idx: usize, buf: &Vec<u8>) -> Result<Chunk> {
-        if idx + 12 > buf.len() {
-            return Err(PngError::WrongFormat(
-                "Buffer containing the image is short".to_string(),
-            ));
-        }
-
-        let length = u32::from_be_bytes(buf[idx..idx + 4].try_into().unwrap());
-        let chunk_type = match std::str::from_utf8(&buf[idx + 4..idx + 8]).unwrap() {
-            "IHDR" => Ok(ChunkType::Ihrd),
-            "PLTE" => Ok(ChunkType::Plte),
-            "IDAT" => Ok(ChunkType::Idat),
-            "IEND" => Ok(ChunkType::Iend),
-            other => Ok(ChunkType::Ancillary(other.to_string())),
-        }?;
-
-        let chunk_data = {
-            if length > 0 {
-                Some(buf[idx + 8..idx + 8 + (length as usize)].to_vec())
-            } else {
-                None
-            }
-        };
-
-        let crc = u32::from_be_bytes(
-            buf[idx + 8 + (length as usize)..idx + 12 + (length as usize)]
-                .try_into()
-                .unwrap(),
-        );
-
-        // Calculate checksum and verify that it is correct
-        if crc32(&buf[idx + 4..idx + 8 + length as usize]) != crc {
-            return Err(PngError::ChecksumFailure);
-        }
-
-        Ok(Chunk {
-            length,
-            chunk_type,
-            chunk_data,
-        })
-    }
-}
-
-#[derive(Debug)]
-/// Color representation as RGB
-pub struct Color {
-    pub red: u8,
-    pub green: u8,
-    pub blue: u8,
-}
-
-#[derive(Debug)]
-/// Representation of a png image file
-pub struct PngImage {
-    pub width: u32,
-    pub height: u32,
-    pub bit_depth: u8,
-    pub color_type: ColorType,
-    compression_method: CompressionMethod,
-    filter_method: FilterMethod,
-    interlace_method: InterlaceMethod,
-    pub palette: Option<Vec<Color>>,
-    pub data: Vec<u8>,
-}
-
-/// Check the png magic header and return () if the buffer contains a .png file,
-/// otherwise return an error
-fn check_if_png(buffer_with_image: &[u8]) -> Result<()> {
-    let png_header: Vec<u8> = vec![0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
-    let buffer_header = &buffer_with_image[..8];
-
-    let matching = png_header
-        .iter()
-        .zip(buffer_header)
-        .filter(|&(a, b)| a == b)
-        .count();
-
-    match matching {
-        8 => Ok(()),
-        _ => Err(PngError::NotAPng),
-    }
-}
-
-/// Read a file, check that it is a .png file and return a buffer containing the
-/// contents of the file
-fn read_file(path: &String) -> Result<Vec<u8>> {
-    let mut f = std::fs::File::open(path).map_err(|_| PngError::CouldNotReadFile)?;
-
-    let mut buffer: Vec<u8> = Vec::new();
-
-    f.read_to_end(&mut buffer)
-        .map_err(|_| PngError::CouldNotReadFile)?;
-
-    check_if_png(&buffer)?;
-
-    Ok(buffer)
-}
-
-/// Parse all chunks contained in a png file and return a Vec<Chunk> containing
-/// them
-fn parse_chunks(img_buf: &Vec<u8>) -> Result<Vec<Chunk>> {
-    let mut res: Vec<Chunk> = Vec::new();
-
-    // png data begins at index 8
-    let mut idx = 8;
-
-    loop {
-        let c = Chunk::from_buffer_index(idx, img_buf)?;
-        idx += (c.length as usize) + 12; // 4 (chunk_length) + 4 (chunk_type)
-                                         // + 4 (crc)
-        res.push(c);
-        // Check the last chunk type we pushed, if it is IEND we stop here
-        // and return the read chunks
-        if matches!(res.last().unwrap().chunk_type, ChunkType::Iend) {
-            break Ok(res);
-        }
-    }
-}
-
-/// Find a PLTE block among the chunks and parse the palette colors, if
-/// no PLTE block is present return None
-fn parse_palette(chunks: &Vec<Chunk>) -> Option<Vec<Color>> {
-    let mut res: Vec<Color> = Vec::new();
-    for chunk in chunks {
-        // Palette chunk found, parse it
-        if matches!(chunk.chunk_type, ChunkType::Plte) {
-            let color_data = chunk.chunk_data.as_ref().unwrap();
-            for idx in (0..color_data.len()).step_by(3) {
-                res.push(Color {
-                    red: color_data[idx],
-                    green: color_data[idx + 1],
-                    blue: color_data[idx + 2],
-                })
-            }
-            return Some(res);
-        }
-    }
-    // We did not find a Palette chunk, return None
-    None
-}
-
-/// Go over all IDAT blocks among the chunks and concatenate all the blocks
-/// into a single Vec<u8>
-fn collect_idat_data(chunks: Vec<Chunk>) -> Vec<u8> {
-    let mut res: Vec<u8> = Vec::new();
-
-    for chunk in chunks {
-        if matches!(chunk.chunk_type, ChunkType::Idat) {
-            res.append(&mut chunk.chunk_data.unwrap());
-        }
-    }
-    res
-}
-
-/// Decompress data and return it
-fn decompress(data: &Vec<u8>) -> Result<Vec<u8>> {
-    let mut decompressed: Vec<u8> = Vec::new();
-    match flate2::read::ZlibDecoder::new(data.as_slice()).read_to_end(&mut decompressed) {
-        Ok(_) => Ok(decompressed),
-        Err(_) => Err(PngError::DecompressionFailed),
-    }
-}
-
-/// Calculate and return the number of bytes needed to contain a scanline excluding
-/// the filter byte. Also return the offset (bytes) to the previous pixel in the scanline
-fn calc_bytes_per_scanline_and_filt_offset(
-    width: u32,
-    bit_depth: u8,
-    color_type: &ColorType,
-) -> Result<(usize, usize)> {
-    match color_type {
-        ColorType::IndexedColor => {
-            let bits_per_scanline = (width * bit_depth as u32) as f32;
-            Ok(((bits_per_scanline / 8.0).ceil() as usize, 1))
-        }
-        ColorType::Grayscale => {
-            let bits_per_scanline = (width * bit_depth as u32) as f32;
-            Ok(((bits_per_scanline / 8.0).ceil() as usize, 1))
-        }
-        ColorType::Truecolor => Ok(((width * bit_depth as u32 / 8 * 3) as usize, 3)),
-        ColorType::TrueColorWithAlpha => Ok(((width * bit_depth as u32 / 8 * 4) as usize, 4)),
-        _ => Err(PngError::NotSupported(
-            "calc_bytes_per_scanline not implemented for this ColorType".to_string(),
-        )),
-    }
-}
-
-/// Return the result of a Paeth predictor with values a,b and c
-fn paeth_predictor(a: i32, b: i32, c: i32) -> i32 {
-    let p = a.wrapping_add(b).wrapping_sub(c);
-    let pa = p.abs_diff(a);
-    let pb = p.abs_diff(b);
-    let pc = p.abs_diff(c);
-    if pa <= pb && pa <= pc {
-        a
-    } else if pb <= pc {
-        b
-    } else {
-        c
-    }
-}
-
-/// Return the value of the A byte according to the png specification The A byte is
-/// defined as the byte to the left of the current byte in the scanline. If we are
-/// in the beginning of a scanline the A byte is 0
-fn get_a(
-    scanline_idx: usize,
-    bytes_per_scanline: usize,
-    offset: usize,
-    byte_idx: usize,
-    rec: &[u8],
-) -> i32 {
-    if byte_idx >= offset {
-        rec[scanline_idx * bytes_per_scanline + byte_idx - offset] as i32
-    } else {
-        0
-    }
-}
-/// Return the value of the B byte according to the png specification The B byte is
-/// defined as the byte "above" the current byte in the scanline, ie the byte at
-/// the same position in the scanline from the previous scanline If we are
-/// on the first scanline there will be no scanline above and B will be 0
-fn get_b(
-    scanline_idx: usize,
-    bytes_per_scanline: usize,
-    _: usize,
-    byte_idx: usize,
-    rec: &[u8],
-) -> i32 {
-    if scanline_idx > 0 {
-        rec[(scanline_idx - 1) * bytes_per_scanline + byte_idx] as i32
-    } else {
-        0
-    }
-}
-/// Return the value of the C byte according to the png specification The C byte is
-/// defined as the byte to the left of the B byte. If we are on the first scanline
-/// or the first byte in a scanline C will be 0
-fn get_c(
-    scanline_idx: usize,
-    bytes_per_scanline: usize,
-    offset: usize,
-    byte_idx: usize,
-    rec: &[u8],
-) -> i32 {
-    if scanline_idx > 0 && byte_idx >= offset {
-        rec[(scanline_idx - 1) * bytes_per_scanline + byte_idx - offset] as i32
-    } else {
-        0
-    }
-}
-
-/// Perform reconstruction on the png image data and return a vector containing
-/// the decoded data
-fn reconstruct(
-    data: &[u8],
-    height: u32,
-    bytes_per_scanline: usize,
-    offset: usize,
-) -> Result<Vec<u8>> {
-    let mut res: Vec<u8> = Vec::new();
-
-    let mut byte_count = 0;
-    for scanline_idx in 0..height as usize {
-        let filter_type = data[byte_count];
-        byte_count += 1;
-
-        for byte_idx in 0..bytes_per_scanline {
-            let x = data[byte_count] as i32;
-            byte_count += 1;
-
-            // A bit unessecary to get these each iteration regardless of
-            // filter type but it looks a little cleaner code-wise
-            let a = get_a(scanline_idx, bytes_per_scanline, offset, byte_idx, &res);
-            let b = get_b(scanline_idx, bytes_per_scanline, offset, byte_idx, &res);
-            let c = get_c(scanline_idx, bytes_per_scanline, offset, byte_idx, &res);
-
-            let filt_x = match filter_type {
-                0 => x,                            // None
-                1 => x + a,                        // Sub
-                2 => x + b,                        // Up
-                3 => x + (a + b) / 2,              // Average
-                4 => x + paeth_predictor(a, b, c), // Paeth
-                _ => return Err(PngError::FilterNotSupported(filter_type)),
-            };
-            res.push((filt_x & 0xFF) as u8);
-        }
-    }
-
-    Ok(res)
-}
-
-/// Parse the contents of a .png file pointed to by path and return a PngImage
-/// struct containing the parsed png image. Note that this does not include
-/// conversion from scanlines to actual RGB values, only decompression and
-/// reconstruction
-pub fn parse_png(path: &String) -> Result<PngImage> {
-    let png_buf = read_file(path)?;
-    let chunks = parse_chunks(&png_buf)?;
-
-    // First index should contain an IHDR
-    let ihdr_chunk = {
-        let chunk = &chunks[0];
-        if !matches!(chunk.chunk_type, ChunkType::Ihrd) {
-            Err(PngError::WrongFormat(
-                "First chunk type != IHDR".to_string(),
-            ))
-        } else if chunk.chunk_data.is_none() {
-            Err(PngError::WrongFormat(
-                "IHDR chunk has no chunk data".to_string(),
-            ))
-        } else if chunk.chunk_data.as_ref().unwrap().len() != 13 {
-            Err(PngError::WrongFormat("IHDR chunk len != 13".to_string()))
-        } else {
-            Ok(chunk)
-        }
-    }?;
-
-    // Parse the metadata from IHDR
-    let ihdr_data = ihdr_chunk.chunk_data.as_ref().unwrap();
-
-    let width = u32::from_be_bytes(ihdr_data[0..4].try_into().unwrap());
-    let height = u32::from_be_bytes(ihdr_data[4..8].try_into().unwrap());
-    let bit_depth = ihdr_data[8];
-    let color_type = match ihdr_data[9] {
-        0 => Ok(ColorType::Grayscale),
-        2 => Ok(ColorType::Truecolor),
-        3 => Ok(ColorType::IndexedColor),
-        4 => Ok(ColorType::GrayScaleWithAlpha),
-        6 => Ok(ColorType::TrueColorWithAlpha),
-        _ => Err(PngError::WrongFormat("Invalid color type".to_string())),
-    }?;
-
-    let compression_method = match ihdr_data[10] {
-        0 => Ok(CompressionMethod::DeflateInflate),
-        _ => Err(PngError::WrongFormat(
-            "Invalid compression method".to_string(),
-        )),
-    }?;
-
-    let filter_method = match ihdr_data[11] {
-        0 => Ok(FilterMethod::FiveTypeAdaptive),
-        _ => Err(PngError::WrongFormat("Invalid filter method".to_string())),
-    }?;
-
-    let interlace_method = match ihdr_data[12] {
-        0 => Ok(InterlaceMethod::NoInterlace),
-        1 => Ok(InterlaceMethod::Adam7Interlace),
-        _ => Err(PngError::WrongFormat(
-            "Invalid interlace method".to_string(),
-        )),
-    }?;
-
-    // We do not support interlacing
-    if matches!(interlace_method, InterlaceMethod::Adam7Interlace) {
-        return Err(PngError::NotSupported("Adam7 interlacing".to_string()));
-    }
-
-    let palette = parse_palette(&chunks);
-
-    // Collect data from all IDAT blocks into a Vec<u8> and perform operations
-    // to reconstruct the image data
-    let idat_data = collect_idat_data(chunks);
-    let decompressed = decompress(&idat_data)?;
-    // let bytes_per_scanline = calc_bytes_per_scanline(width, bit_depth, &color_type)?;
-    let (bytes_per_pixel, filt_offset) =
-        calc_bytes_per_scanline_and_filt_offset(width, bit_depth, &color_type)?;
-    let data = reconstruct(&decompressed, height, bytes_per_pixel, filt_offset)?;
-
-    Ok(PngImage {
-        width,
-        height,
-        bit_depth,
-        color_type,
-        compression_method,
-        filter_method,
-        interlace_method,
-        palette,
-        data,
-    })
-}
+use std::io::Read;
+
+pub type Result<T> = std::result::Result<T, PngError>;
+
+#[derive(Debug)]
+pub enum PngError {
+    CouldNotReadFile,
+    ChecksumFailure,
+    NotAPng,
+    WrongFormat(String),
+    FilterNotSupported(u8),
+    DecompressionFailed,
+    CompressionFailed,
+    NotSupported(String),
+    UnsupportedCompression(u8),
+    CouldNotWriteFile,
+    TruncatedData,
+}
+
+impl PngError {
+    /// Return the exit code used by the command line tool for this error.
+    ///
+    /// | Code | Error                    |
+    /// |------|--------------------------|
+    /// | 3    | `CouldNotReadFile`       |
+    /// | 4    | `NotAPng`                |
+    /// | 5    | `ChecksumFailure`        |
+    /// | 6    | `WrongFormat`            |
+    /// | 7    | `FilterNotSupported`     |
+    /// | 8    | `DecompressionFailed`    |
+    /// | 9    | `CompressionFailed`      |
+    /// | 10   | `NotSupported`           |
+    /// | 11   | `UnsupportedCompression` |
+    /// | 12   | `CouldNotWriteFile`      |
+    /// | 13   | `TruncatedData`          |
+    ///
+    /// Code 1 is left for panics and 2 for incorrect usage
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            PngError::CouldNotReadFile => 3,
+            PngError::NotAPng => 4,
+            PngError::ChecksumFailure => 5,
+            PngError::WrongFormat(_) => 6,
+            PngError::FilterNotSupported(_) => 7,
+            PngError::DecompressionFailed => 8,
+            PngError::CompressionFailed => 9,
+            PngError::NotSupported(_) => 10,
+            PngError::UnsupportedCompression(_) => 11,
+            PngError::CouldNotWriteFile => 12,
+            PngError::TruncatedData => 13,
+        }
+    }
+}
+
+impl std::error::Error for PngError {}
+
+impl std::fmt::Display for PngError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PngError::CouldNotReadFile => write!(f, "Could not read the file"),
+            PngError::ChecksumFailure => write!(f, "Checksum incorrect"),
+            PngError::NotAPng => write!(f, "File is not png format"),
+            PngError::WrongFormat(t) => write!(f, "Incorrect png format: {}", t),
+            PngError::FilterNotSupported(t) => write!(f, "Filter type {} not supported", t),
+            PngError::DecompressionFailed => write!(f, "Decompression failed!"),
+            PngError::CompressionFailed => write!(f, "Compression failed!"),
+            PngError::NotSupported(t) => write!(f, "Not supported: {}", t),
+            PngError::UnsupportedCompression(t) => {
+                write!(f, "Compression method {} not supported", t)
+            }
+            PngError::CouldNotWriteFile => write!(f, "Could not write the file"),
+            PngError::TruncatedData => write!(f, "Image data ends before the last scanline"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorType {
+    Grayscale,
+    Truecolor,
+    IndexedColor,
+    GrayScaleWithAlpha,
+    TrueColorWithAlpha,
+}
+
+impl std::fmt::Display for ColorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ColorType::Grayscale => write!(f, "Grayscale"),
+            ColorType::Truecolor => write!(f, "Truecolor"),
+            ColorType::IndexedColor => write!(f, "Indexed color"),
+            ColorType::GrayScaleWithAlpha => write!(f, "Grayscale with alpha"),
+            ColorType::TrueColorWithAlpha => write!(f, "Truecolor with alpha"),
+        }
+    }
+}
+
+impl ColorType {
+    /// Return the number of samples stored per pixel, a palette index counts
+    /// as a single sample
+    pub(crate) fn channels(&self) -> usize {
+        match self {
+            ColorType::Grayscale | ColorType::IndexedColor => 1,
+            ColorType::GrayScaleWithAlpha => 2,
+            ColorType::Truecolor => 3,
+            ColorType::TrueColorWithAlpha => 4,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum CompressionMethod {
+    DeflateInflate,
+}
+
+#[derive(Debug)]
+enum FilterMethod {
+    FiveTypeAdaptive,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InterlaceMethod {
+    NoInterlace,
+    Adam7Interlace,
+}
+
+#[derive(Debug)]
+enum ChunkType {
+    Ihrd,
+    Plte,
+    Idat,
+    Iend,
+    Offs,
+    Scal,
+    Gama,
+    Srgb,
+    Actl,
+    Fctl,
+    Fdat,
+    Trns,
+    Text,
+    Ztxt,
+    Phys,
+    Bkgd,
+    Sbit,
+    Itxt,
+    Time,
+    Hist,
+    Iccp,
+    Ancillary(String),
+}
+
+impl ChunkType {
+    /// Return the chunk type with the four letter name stored in bytes. Chunk
+    /// types consist of ASCII letters only, anything else is an error
+    fn from_bytes(bytes: &[u8]) -> Result<ChunkType> {
+        if bytes.len() != 4 || !bytes.iter().all(u8::is_ascii_alphabetic) {
+            return Err(PngError::WrongFormat("Invalid chunk type".to_string()));
+        }
+
+        // Only ASCII letters remain so the name is valid UTF-8
+        match std::str::from_utf8(bytes).unwrap() {
+            "IHDR" => Ok(ChunkType::Ihrd),
+            "PLTE" => Ok(ChunkType::Plte),
+            "IDAT" => Ok(ChunkType::Idat),
+            "IEND" => Ok(ChunkType::Iend),
+            "oFFs" => Ok(ChunkType::Offs),
+            "sCAL" => Ok(ChunkType::Scal),
+            "gAMA" => Ok(ChunkType::Gama),
+            "sRGB" => Ok(ChunkType::Srgb),
+            "acTL" => Ok(ChunkType::Actl),
+            "fcTL" => Ok(ChunkType::Fctl),
+            "fdAT" => Ok(ChunkType::Fdat),
+            "tRNS" => Ok(ChunkType::Trns),
+            "tEXt" => Ok(ChunkType::Text),
+            "zTXt" => Ok(ChunkType::Ztxt),
+            "pHYs" => Ok(ChunkType::Phys),
+            "bKGD" => Ok(ChunkType::Bkgd),
+            "sBIT" => Ok(ChunkType::Sbit),
+            "iTXt" => Ok(ChunkType::Itxt),
+            "tIME" => Ok(ChunkType::Time),
+            "hIST" => Ok(ChunkType::Hist),
+            "iCCP" => Ok(ChunkType::Iccp),
+            other => Ok(ChunkType::Ancillary(other.to_string())),
+        }
+    }
+
+    /// Return the four letter name of the chunk type as stored in the file
+    fn name(&self) -> &str {
+        match self {
+            ChunkType::Ihrd => "IHDR",
+            ChunkType::Plte => "PLTE",
+            ChunkType::Idat => "IDAT",
+            ChunkType::Iend => "IEND",
+            ChunkType::Offs => "oFFs",
+            ChunkType::Scal => "sCAL",
+            ChunkType::Gama => "gAMA",
+            ChunkType::Srgb => "sRGB",
+            ChunkType::Actl => "acTL",
+            ChunkType::Fctl => "fcTL",
+            ChunkType::Fdat => "fdAT",
+            ChunkType::Trns => "tRNS",
+            ChunkType::Text => "tEXt",
+            ChunkType::Ztxt => "zTXt",
+            ChunkType::Phys => "pHYs",
+            ChunkType::Bkgd => "bKGD",
+            ChunkType::Sbit => "sBIT",
+            ChunkType::Itxt => "iTXt",
+            ChunkType::Time => "tIME",
+            ChunkType::Hist => "hIST",
+            ChunkType::Iccp => "iCCP",
+            ChunkType::Ancillary(name) => name,
+        }
+    }
+}
+
+/// Build the lookup table for crc32, entry n is the crc of the single byte n
+/// before the final inversion
+const fn make_crc_table() -> [u32; 256] {
+    let mut table = [0_u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 1 {
+                0xEDB88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+const CRC_TABLE: [u32; 256] = make_crc_table();
+
+/// Calculate crc32 checksum for the bytes in seq using a lookup table as in
+/// the sample code of the png specification:
+/// https://www.w3.org/TR/2003/REC-PNG-20031110/#D-CRCAppendix
+pub(crate) fn crc32(seq: &[u8]) -> u32 {
+    !crc32_update(0xFFFFFFFF, seq)
+}
+
+/// Continue a crc32 calculation with the bytes in seq, which allows the
+/// checksum of data that is read piece by piece to be calculated. Start with
+/// 0xFFFFFFFF and invert the final value to get the checksum
+fn crc32_update(crc: u32, seq: &[u8]) -> u32 {
+    seq.iter().fold(crc, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+/// What to do when the checksum stored with a chunk does not match its contents
+pub enum CrcPolicy {
+    /// Fail parsing with ChecksumFailure
+    #[default]
+    Fail,
+    /// Keep parsing and record the chunk type, the recorded types are
+    /// returned by PngImage::crc_mismatches. Meant for files written by
+    /// tools known to store incorrect checksums
+    Warn,
+    /// Do not calculate the checksums at all, which speeds up parsing of
+    /// files from a trusted source
+    Ignore,
+}
+
+/// Checks the checksums of the chunks of a file according to a CrcPolicy and
+/// remembers the types of the chunks whose checksum did not match
+struct CrcCheck {
+    policy: CrcPolicy,
+    mismatches: Vec<String>,
+}
+
+impl CrcCheck {
+    fn new(policy: CrcPolicy) -> CrcCheck {
+        CrcCheck {
+            policy,
+            mismatches: Vec::new(),
+        }
+    }
+
+    /// Compare the checksum stored with a chunk against the one calculated by
+    /// calculate, which is not called if checksums are ignored
+    fn check<F: FnOnce() -> u32>(
+        &mut self,
+        chunk_type: &str,
+        stored: u32,
+        calculate: F,
+    ) -> Result<()> {
+        if self.policy == CrcPolicy::Ignore || calculate() == stored {
+            return Ok(());
+        }
+        match self.policy {
+            CrcPolicy::Warn => {
+                self.mismatches.push(chunk_type.to_string());
+                Ok(())
+            }
+            _ => Err(PngError::ChecksumFailure),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// struct representing a raw chunk of a png file
+struct Chunk {
+    length: u32,
+    chunk_type: ChunkType,
+    chunk_data: Option<Vec<u8>>,
+}
+
+impl Chunk {
+    /// Construct a Chunk from a buffer and a starting index, the checksum is
+    /// checked with crc_check
+    fn from_buffer_index(idx: usize, buf: &[u8], crc_check: &mut CrcCheck) -> Result<Chunk> {
+        if idx.checked_add(12).is_none_or(|end| end > buf.len()) {
+            return Err(PngError::WrongFormat(
+                "Buffer containing the image is short".to_string(),
+            ));
+        }
+
+        // Checked arithmetic since a corrupt length close to 4 GB could
+        // overflow usize on 32-bit targets
+        let length = u32::from_be_bytes(buf[idx..idx + 4].try_into().unwrap());
+        let chunk_end = usize::try_from(length)
+            .ok()
+            .and_then(|length| (idx + 12).checked_add(length));
+        if chunk_end.is_none_or(|end| end > buf.len()) {
+            return Err(PngError::WrongFormat(
+                "Chunk extends past the end of the buffer".to_string(),
+            ));
+        }
+        let chunk_type = ChunkType::from_bytes(&buf[idx + 4..idx + 8])?;
+
+        let chunk_data = {
+            if length > 0 {
+                Some(buf[idx + 8..idx + 8 + (length as usize)].to_vec())
+            } else {
+                None
+            }
+        };
+
+        let crc = u32::from_be_bytes(
+            buf[idx + 8 + (length as usize)..idx + 12 + (length as usize)]
+                .try_into()
+                .unwrap(),
+        );
+        crc_check.check(chunk_type.name(), crc, || {
+            crc32(&buf[idx + 4..idx + 8 + length as usize])
+        })?;
+
+        Ok(Chunk {
+            length,
+            chunk_type,
+            chunk_data,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Position and checksum status of a single chunk of a png file, where offset
+/// is the index of the chunk's length field in the file and length is the
+/// length of its data
+pub struct ChunkInfo {
+    pub chunk_type: String,
+    pub length: u32,
+    pub crc_ok: bool,
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Color representation as RGBA, alpha is 255 for opaque colors
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    pub alpha: u8,
+}
+
+#[derive(Debug)]
+pub enum OffsetUnit {
+    Pixel,
+    Micrometre,
+}
+
+#[derive(Debug)]
+/// Image position given by an oFFs chunk
+pub struct ImageOffset {
+    pub x: i32,
+    pub y: i32,
+    pub unit: OffsetUnit,
+}
+
+#[derive(Debug)]
+pub enum ScaleUnit {
+    Metre,
+    Radian,
+}
+
+#[derive(Debug)]
+/// Physical size of a pixel given by a sCAL chunk. The width and height are
+/// kept as the ASCII floating-point strings stored in the file
+pub struct PhysicalScale {
+    pub unit: ScaleUnit,
+    pub width: String,
+    pub height: String,
+}
+
+impl PhysicalScale {
+    /// Return the pixel width as a float
+    pub fn width_value(&self) -> f64 {
+        self.width.parse().unwrap_or(f64::NAN)
+    }
+
+    /// Return the pixel height as a float
+    pub fn height_value(&self) -> f64 {
+        self.height.parse().unwrap_or(f64::NAN)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Keyword and text of a tEXt, zTXt or iTXt chunk, the text of compressed
+/// chunks is decompressed. Only iTXt chunks carry a language tag and a
+/// translation of the keyword, which may be empty
+pub struct TextChunk {
+    pub keyword: String,
+    pub text: String,
+    pub language_tag: Option<String>,
+    pub translated_keyword: Option<String>,
+}
+
+/// Time of the last modification of an image from its tIME chunk as (year,
+/// month, day, hour, minute, second) in UTC
+pub type Timestamp = (u16, u8, u8, u8, u8, u8);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Frame control information of an animated png given by a fcTL chunk
+pub struct Frame {
+    pub sequence_number: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    pub delay_num: u16,
+    pub delay_den: u16,
+    pub dispose_op: u8,
+    pub blend_op: u8,
+}
+
+impl Frame {
+    /// Return the time to display the frame in milliseconds. A denominator of
+    /// 0 means the delay is given in hundredths of a second
+    pub fn delay_ms(&self) -> f64 {
+        let den = match self.delay_den {
+            0 => 100,
+            den => den,
+        };
+        self.delay_num as f64 / den as f64 * 1000.0
+    }
+}
+
+#[derive(Debug)]
+/// Representation of a png image file
+pub struct PngImage {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    #[allow(dead_code)]
+    compression_method: CompressionMethod,
+    #[allow(dead_code)]
+    filter_method: FilterMethod,
+    interlace_method: InterlaceMethod,
+    pub palette: Option<Vec<Color>>,
+    pub data: Vec<u8>,
+    offset: Option<ImageOffset>,
+    scale: Option<PhysicalScale>,
+    gamma: Option<u32>,
+    srgb_intent: Option<u8>,
+    frames: Vec<Frame>,
+    animation_control: Option<(u32, u32)>,
+    transparency: Option<Vec<u8>>,
+    text: Vec<TextChunk>,
+    physical_dimensions: Option<(u32, u32, u8)>,
+    background: Option<Color>,
+    significant_bits: Option<Vec<u8>>,
+    modification_time: Option<Timestamp>,
+    histogram: Option<Vec<u16>>,
+    icc_profile: Option<(String, Vec<u8>)>,
+    raw_idat: Option<Vec<u8>>,
+    ancillary_chunks: Vec<String>,
+    crc_mismatches: Vec<String>,
+}
+
+impl PngImage {
+    /// Convert an image with an alpha channel into the equivalent color type
+    /// without alpha by removing the alpha samples from the data. For images
+    /// without an alpha channel the tRNS transparency is removed instead
+    pub fn drop_alpha(self) -> PngImage {
+        let color_type = match self.color_type {
+            ColorType::TrueColorWithAlpha => ColorType::Truecolor,
+            ColorType::GrayScaleWithAlpha => ColorType::Grayscale,
+            _ => {
+                let palette = self.palette.map(|palette| {
+                    palette
+                        .into_iter()
+                        .map(|color| Color {
+                            alpha: 255,
+                            ..color
+                        })
+                        .collect()
+                });
+                return PngImage {
+                    palette,
+                    transparency: None,
+                    ..self
+                };
+            }
+        };
+
+        // Alpha is always the last sample of a pixel
+        let bytes_per_pixel = self.bytes_per_pixel();
+        let bytes_per_channel = bytes_per_pixel / self.channels();
+        let data = self
+            .data
+            .chunks(bytes_per_pixel)
+            .flat_map(|pixel| &pixel[..bytes_per_pixel - bytes_per_channel])
+            .copied()
+            .collect();
+
+        // The significant bits of alpha are the last entry of sBIT
+        let significant_bits = self.significant_bits.map(|mut bits| {
+            bits.pop();
+            bits
+        });
+
+        PngImage {
+            color_type,
+            data,
+            significant_bits,
+            raw_idat: None,
+            ..self
+        }
+    }
+
+    /// Update the header after the samples have been reduced from 16 to 8
+    /// bits. Reduction keeps the most significant bits of every sample, so at
+    /// most 8 of them remain significant. The transparent color of tRNS is
+    /// reduced the same way, which makes every color that reduces to it
+    /// transparent
+    fn reduced_to_8bit(&mut self) {
+        self.bit_depth = 8;
+        self.raw_idat = None;
+        if let Some(bits) = self.significant_bits.as_mut() {
+            bits.iter_mut().for_each(|bits| *bits = (*bits).min(8));
+        }
+        if !matches!(self.color_type, ColorType::IndexedColor) {
+            if let Some(trns) = self.transparency.as_mut() {
+                trns.chunks_mut(2).for_each(|sample| {
+                    sample[1] = sample[0];
+                    sample[0] = 0;
+                });
+            }
+        }
+    }
+
+    /// Return a human readable description of the image header and the
+    /// ancillary chunks found in the file, without the pixel data
+    pub fn summary(&self) -> String {
+        let palette_size = match &self.palette {
+            Some(palette) => palette.len().to_string(),
+            None => "none".to_string(),
+        };
+        let ancillary_chunks = if self.ancillary_chunks.is_empty() {
+            "none".to_string()
+        } else {
+            self.ancillary_chunks.join(", ")
+        };
+        let interlace_method = match self.interlace_method {
+            InterlaceMethod::NoInterlace => "none",
+            InterlaceMethod::Adam7Interlace => "Adam7",
+        };
+
+        format!(
+            "Dimensions: {}x{}\n\
+             Bit depth: {}\n\
+             Color type: {}\n\
+             Interlace method: {}\n\
+             Palette size: {}\n\
+             Ancillary chunks: {}",
+            self.width,
+            self.height,
+            self.bit_depth,
+            self.color_type,
+            interlace_method,
+            palette_size,
+            ancillary_chunks
+        )
+    }
+
+    /// Return the number of entries in the palette, or 0 if the image has
+    /// none. The palette may be shorter than the bit depth allows, indices
+    /// past its end are only an error if a pixel actually uses them
+    pub fn palette_len(&self) -> usize {
+        self.palette.as_ref().map_or(0, Vec::len)
+    }
+
+    /// Return the number of samples stored per pixel: 1 for grayscale and
+    /// indexed color, 2 for grayscale with alpha, 3 for truecolor and 4 for
+    /// truecolor with alpha
+    pub fn channels(&self) -> usize {
+        self.color_type.channels()
+    }
+
+    /// Return the number of bytes a pixel takes up in the data, rounded up to
+    /// 1 for bit depths below 8. This is also the distance to the previous
+    /// pixel used by the scanline filters
+    pub fn bytes_per_pixel(&self) -> usize {
+        bits_per_pixel(self.bit_depth, &self.color_type).div_ceil(8)
+    }
+
+    /// Return the color type of the image
+    pub fn color_type(&self) -> ColorType {
+        self.color_type
+    }
+
+    /// Return the interlace method the image is stored with in the file
+    pub fn interlace_method(&self) -> InterlaceMethod {
+        self.interlace_method
+    }
+
+    /// Return true if the image is stored Adam7 interlaced in the file. The
+    /// data of a parsed image is always laid out as for a non-interlaced image
+    pub fn is_interlaced(&self) -> bool {
+        matches!(self.interlace_method, InterlaceMethod::Adam7Interlace)
+    }
+
+    /// Return the image offset from the oFFs chunk, if present
+    pub fn offset(&self) -> Option<&ImageOffset> {
+        self.offset.as_ref()
+    }
+
+    /// Return the physical pixel scale from the sCAL chunk, if present
+    pub fn scale(&self) -> Option<&PhysicalScale> {
+        self.scale.as_ref()
+    }
+
+    /// Return the image gamma from the gAMA chunk, if present
+    pub fn gamma(&self) -> Option<f64> {
+        self.gamma.map(|gamma| gamma as f64 / 100000.0)
+    }
+
+    /// Return the rendering intent from the sRGB chunk, if present
+    pub fn srgb_intent(&self) -> Option<u8> {
+        self.srgb_intent
+    }
+
+    /// Return the time of the last modification from the tIME chunk as
+    /// (year, month, day, hour, minute, second) in UTC, if present
+    pub fn modification_time(&self) -> Option<Timestamp> {
+        self.modification_time
+    }
+
+    /// Return the approximate usage frequency of every palette entry from the
+    /// hIST chunk, if present. Entries are in palette order
+    pub fn histogram(&self) -> Option<&[u16]> {
+        self.histogram.as_deref()
+    }
+
+    /// Return the name and the decompressed ICC profile from the iCCP chunk,
+    /// if present
+    pub fn icc_profile(&self) -> Option<(&str, &[u8])> {
+        self.icc_profile
+            .as_ref()
+            .map(|(name, profile)| (name.as_str(), profile.as_slice()))
+    }
+
+    /// Return the compressed data of the IDAT chunks concatenated as stored in
+    /// the file, if the image was parsed with keep_raw_idat. It is dropped
+    /// once the data no longer matches it, such as after drop_alpha removed
+    /// the alpha samples or 16-bit samples were reduced to 8 bits
+    pub fn raw_idat(&self) -> Option<&[u8]> {
+        self.raw_idat.as_deref()
+    }
+
+    /// Return the frame control information of every fcTL chunk in the order
+    /// they appear, empty unless the image is animated
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Return the number of frames of an animated png from its acTL chunk, or
+    /// None if the image is not animated. The default image decoded from the
+    /// IDAT chunks only counts as a frame if a fcTL chunk precedes it, the
+    /// other frames are stored in fdAT chunks and are not decoded
+    pub fn frame_count(&self) -> Option<u32> {
+        self.animation_control.map(|(frames, _)| frames)
+    }
+
+    /// Return the number of times an animated png should be played from its
+    /// acTL chunk, where 0 means it loops forever, or None if the image is
+    /// not animated
+    pub fn play_count(&self) -> Option<u32> {
+        self.animation_control.map(|(_, plays)| plays)
+    }
+
+    /// Return the contents of the tRNS chunk, if present. For indexed color
+    /// images this is the alpha of the palette entries, which is also applied
+    /// to the palette, for grayscale and truecolor images it is the 16-bit
+    /// samples of the single color that is fully transparent
+    pub fn transparency(&self) -> Option<&[u8]> {
+        self.transparency.as_deref()
+    }
+
+    /// Return every tEXt, zTXt and iTXt chunk in the order they appear, a
+    /// keyword may occur more than once
+    pub fn text(&self) -> &[TextChunk] {
+        &self.text
+    }
+
+    /// Return the pixels per unit along the x and y axis and the unit from the
+    /// pHYs chunk, if present. Unit 1 is the meter while 0 means the unit is
+    /// unknown and only the aspect ratio of the pixels is given
+    pub fn physical_dimensions(&self) -> Option<(u32, u32, u8)> {
+        self.physical_dimensions
+    }
+
+    /// Return the suggested background color from the bKGD chunk, if present.
+    /// Samples are scaled to 8 bits the same way the decoder scales pixels
+    pub fn background(&self) -> Option<Color> {
+        self.background
+    }
+
+    /// Return the number of significant bits of every channel from the sBIT
+    /// chunk, if present. There is one entry per sample of the color type, in
+    /// the order the samples are stored, and three for the palette colors of
+    /// indexed images. A sample with n significant bits is rescaled to the
+    /// full range by shifting it right by bit_depth - n and stretching the
+    /// n-bit result over the range of the bit depth
+    pub fn significant_bits(&self) -> Option<&[u8]> {
+        self.significant_bits.as_deref()
+    }
+
+    /// Return the type of every chunk whose checksum did not match, in the
+    /// order they appear. Only filled when parsing with CrcPolicy::Warn
+    pub fn crc_mismatches(&self) -> &[String] {
+        &self.crc_mismatches
+    }
+
+    /// Return the horizontal and vertical resolution in dots per inch, if the
+    /// image has a pHYs chunk given in pixels per meter
+    pub fn dpi(&self) -> Option<(f64, f64)> {
+        match self.physical_dimensions {
+            Some((x, y, 1)) => Some((x as f64 * 0.0254, y as f64 * 0.0254)),
+            _ => None,
+        }
+    }
+
+    /// Return the transfer function the samples are encoded with. An sRGB
+    /// chunk takes precedence over a gAMA chunk as required by the png
+    /// specification, if neither is present the transfer is unknown
+    pub fn effective_transfer(&self) -> Transfer {
+        match (self.srgb_intent, self.gamma()) {
+            (Some(_), _) => Transfer::Srgb,
+            (None, Some(gamma)) => Transfer::Gamma(gamma),
+            (None, None) => Transfer::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Transfer function relating stored samples to linear light
+pub enum Transfer {
+    /// The sRGB transfer function
+    Srgb,
+    /// A power law with the given encoding gamma, as stored in gAMA
+    Gamma(f64),
+    /// No color space information is present
+    Unknown,
+}
+
+impl Transfer {
+    /// Convert a sample in the range 0.0..1.0 to linear light. Samples are
+    /// returned unchanged when the transfer is unknown
+    pub fn to_linear(&self, sample: f64) -> f64 {
+        match self {
+            Transfer::Srgb if sample <= 0.04045 => sample / 12.92,
+            Transfer::Srgb => ((sample + 0.055) / 1.055).powf(2.4),
+            Transfer::Gamma(gamma) => sample.powf(1.0 / gamma),
+            Transfer::Unknown => sample,
+        }
+    }
+}
+
+/// Reconstruction function for filter types not defined by the png
+/// specification. It is called with the filter type, the filtered byte x and
+/// the neighbouring bytes a, b and c, and returns the reconstructed byte
+pub type CustomFilter = dyn Fn(u8, i32, i32, i32, i32) -> i32;
+
+#[derive(Default)]
+/// Options controlling how a png file is parsed
+pub struct ParseOptions {
+    /// Reject files that are technically malformed even if they could be
+    /// read anyway, such as fixed-length chunks with the wrong length
+    pub strict: bool,
+    /// Scale 16-bit samples down to 8 bits right after reconstruction, which
+    /// halves the memory used by the image data at the cost of precision
+    pub reduce_to_8bit: bool,
+    /// Used to reconstruct scanlines with a filter type above 4 instead of
+    /// failing with FilterNotSupported
+    pub custom_filter: Option<Box<CustomFilter>>,
+    /// Best-effort reading of corrupt files, chunks in front of the first
+    /// IHDR chunk are skipped instead of rejecting the file. Has no effect
+    /// together with strict
+    pub recovery: bool,
+    /// What to do with chunks whose checksum does not match their contents
+    pub on_crc_error: CrcPolicy,
+    /// Largest width accepted, wider images fail with NotSupported before
+    /// any image data is decompressed. None means no limit
+    pub max_width: Option<u32>,
+    /// Largest height accepted, None means no limit
+    pub max_height: Option<u32>,
+    /// Largest number of pixels accepted, which guards against files that
+    /// claim huge dimensions to make the decoder exhaust memory. None means
+    /// no limit
+    pub max_pixels: Option<u64>,
+    /// Keep the compressed data of the IDAT chunks on the image, so that
+    /// encode_passthrough can write it out again without recompressing it
+    pub keep_raw_idat: bool,
+}
+
+impl std::fmt::Debug for ParseOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ParseOptions")
+            .field("strict", &self.strict)
+            .field("reduce_to_8bit", &self.reduce_to_8bit)
+            .field("custom_filter", &self.custom_filter.is_some())
+            .field("recovery", &self.recovery)
+            .field("on_crc_error", &self.on_crc_error)
+            .field("max_width", &self.max_width)
+            .field("max_height", &self.max_height)
+            .field("max_pixels", &self.max_pixels)
+            .field("keep_raw_idat", &self.keep_raw_idat)
+            .finish()
+    }
+}
+
+/// Return the length the data of a chunk type must have, or None if the length
+/// is variable or the chunk type is unknown. Some chunk types have a length
+/// that depends on the color type of the image
+fn expected_length(chunk_type: &ChunkType, color_type: &ColorType) -> Option<usize> {
+    match (chunk_type.name(), color_type) {
+        ("IHDR", _) => Some(13),
+        ("IEND", _) => Some(0),
+        ("gAMA", _) => Some(4),
+        ("sRGB", _) => Some(1),
+        ("pHYs", _) => Some(9),
+        ("oFFs", _) => Some(9),
+        ("tIME", _) => Some(7),
+        ("cHRM", _) => Some(32),
+        ("acTL", _) => Some(8),
+        ("fcTL", _) => Some(26),
+        ("bKGD", ColorType::IndexedColor) => Some(1),
+        ("bKGD", ColorType::Grayscale | ColorType::GrayScaleWithAlpha) => Some(2),
+        ("bKGD", ColorType::Truecolor | ColorType::TrueColorWithAlpha) => Some(6),
+        ("tRNS", ColorType::Grayscale) => Some(2),
+        ("tRNS", ColorType::Truecolor) => Some(6),
+        ("sBIT", ColorType::Grayscale) => Some(1),
+        ("sBIT", ColorType::GrayScaleWithAlpha) => Some(2),
+        ("sBIT", ColorType::Truecolor | ColorType::IndexedColor) => Some(3),
+        ("sBIT", ColorType::TrueColorWithAlpha) => Some(4),
+        _ => None,
+    }
+}
+
+/// Verify that every chunk with a fixed length has exactly that length
+fn check_chunk_lengths(chunks: &[Chunk], color_type: &ColorType) -> Result<()> {
+    for chunk in chunks {
+        if let Some(expected) = expected_length(&chunk.chunk_type, color_type) {
+            if chunk.length as usize != expected {
+                return Err(PngError::WrongFormat(format!(
+                    "{} chunk len {} != {}",
+                    chunk.chunk_type.name(),
+                    chunk.length,
+                    expected
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Verify that the critical chunks follow the order IHDR, an optional PLTE,
+/// one or more IDAT and finally IEND. Ancillary chunks may appear anywhere in
+/// between and are not considered
+fn check_critical_chunk_order(chunks: &[Chunk]) -> Result<()> {
+    // Position of each critical chunk type in the expected order
+    let mut last_rank: Option<u8> = None;
+
+    for chunk in chunks {
+        let rank = match chunk.chunk_type {
+            ChunkType::Ihrd => 0,
+            ChunkType::Plte => 1,
+            ChunkType::Idat => 2,
+            ChunkType::Iend => 3,
+            _ => continue,
+        };
+
+        let in_order = match (last_rank, rank) {
+            (None, 0) => true,
+            // IDAT is the only critical chunk that may be repeated
+            (Some(last), 2) => last <= 2,
+            // IEND must be preceded by at least one IDAT
+            (Some(last), 3) => last == 2,
+            (Some(last), rank) => last < rank,
+            (None, _) => false,
+        };
+        if !in_order {
+            return Err(PngError::WrongFormat(format!(
+                "{} chunk out of place",
+                chunk.chunk_type.name()
+            )));
+        }
+        last_rank = Some(rank);
+    }
+    Ok(())
+}
+
+/// Verify that the chunks contain image data and that the IDAT chunks are
+/// consecutive, as the png specification requires. Unlike the checks of
+/// strict parsing this is done for every file, since the image data is read
+/// as a single stream
+fn check_image_data_chunks(chunks: &[Chunk]) -> Result<()> {
+    let is_idat = |chunk: &Chunk| matches!(chunk.chunk_type, ChunkType::Idat);
+    let first = chunks.iter().position(is_idat);
+    let last = chunks.iter().rposition(is_idat);
+
+    match (first, last) {
+        (Some(first), Some(last)) if chunks[first..=last].iter().all(is_idat) => Ok(()),
+        (Some(_), _) => Err(PngError::WrongFormat(
+            "IDAT chunks are not consecutive".to_string(),
+        )),
+        _ => Err(PngError::WrongFormat("Missing IDAT chunk".to_string())),
+    }
+}
+
+/// Check the png magic header and return () if the buffer contains a .png file,
+/// otherwise return an error
+fn check_if_png(buffer_with_image: &[u8]) -> Result<()> {
+    if buffer_with_image.len() < 8 {
+        return Err(PngError::NotAPng);
+    }
+
+    let png_header: Vec<u8> = vec![0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+    let buffer_header = &buffer_with_image[..8];
+
+    let matching = png_header
+        .iter()
+        .zip(buffer_header)
+        .filter(|&(a, b)| a == b)
+        .count();
+
+    match matching {
+        8 => Ok(()),
+        _ => Err(PngError::NotAPng),
+    }
+}
+
+/// Parse all chunks contained in a png file and return a Vec<Chunk> containing
+/// them together with the index of the first byte after the IEND chunk
+fn parse_chunks(img_buf: &[u8], crc_check: &mut CrcCheck) -> Result<(Vec<Chunk>, usize)> {
+    let mut res: Vec<Chunk> = Vec::new();
+
+    // png data begins at index 8
+    let mut idx = 8;
+
+    loop {
+        // Running out of data before IEND means the file is truncated
+        if idx >= img_buf.len() {
+            break Err(PngError::WrongFormat("Missing IEND chunk".to_string()));
+        }
+
+        let c = Chunk::from_buffer_index(idx, img_buf, crc_check)?;
+        idx += (c.length as usize) + 12; // 4 (chunk_length) + 4 (chunk_type)
+                                         // + 4 (crc)
+        res.push(c);
+        // Check the last chunk type we pushed, if it is IEND we stop here
+        // and return the read chunks
+        if matches!(res.last().unwrap().chunk_type, ChunkType::Iend) {
+            break Ok((res, idx));
+        }
+    }
+}
+
+/// Read the length and the type of the next chunk from reader. Running out of
+/// data means the file ends before its IEND chunk
+fn read_chunk_header<R: Read>(reader: &mut R) -> Result<(u32, [u8; 4])> {
+    let mut header = [0_u8; 8];
+    reader
+        .read_exact(&mut header)
+        .map_err(|error| match error.kind() {
+            std::io::ErrorKind::UnexpectedEof => {
+                PngError::WrongFormat("Missing IEND chunk".to_string())
+            }
+            _ => PngError::CouldNotReadFile,
+        })?;
+
+    // The png specification limits chunk lengths to 2^31 - 1 bytes
+    let length = u32::from_be_bytes(header[..4].try_into().unwrap());
+    if length > i32::MAX as u32 {
+        return Err(PngError::WrongFormat("Chunk length too large".to_string()));
+    }
+    Ok((length, header[4..].try_into().unwrap()))
+}
+
+/// Read the data and the checksum of a chunk from reader, after its length
+/// and type have been read with read_chunk_header
+fn read_chunk_body<R: Read>(
+    reader: &mut R,
+    length: u32,
+    chunk_type: [u8; 4],
+    crc_check: &mut CrcCheck,
+) -> Result<Chunk> {
+    // Read through take so a corrupt length cannot make us allocate more
+    // than the file actually contains
+    let mut data: Vec<u8> = Vec::new();
+    reader
+        .take(length as u64 + 4)
+        .read_to_end(&mut data)
+        .map_err(|_| PngError::CouldNotReadFile)?;
+    if data.len() != length as usize + 4 {
+        return Err(PngError::WrongFormat(
+            "Chunk extends past the end of the file".to_string(),
+        ));
+    }
+    let crc = u32::from_be_bytes(data.split_off(length as usize).try_into().unwrap());
+
+    crc_check.check(&String::from_utf8_lossy(&chunk_type), crc, || {
+        !crc32_update(crc32_update(0xFFFFFFFF, &chunk_type), &data)
+    })?;
+
+    Ok(Chunk {
+        length,
+        chunk_type: ChunkType::from_bytes(&chunk_type)?,
+        chunk_data: if length > 0 { Some(data) } else { None },
+    })
+}
+
+/// Reader over the data of consecutive IDAT chunks read from an underlying
+/// reader, so the image data can be decompressed while it is read instead of
+/// being collected first. The checksum of every IDAT chunk is verified once
+/// all its data has been read. Reading ends at the first chunk of another
+/// type, whose length and type are kept in next_chunk
+struct IdatReader<R: Read> {
+    reader: R,
+    crc_check: CrcCheck,
+    /// Bytes left of the data of the current IDAT chunk
+    remaining: u32,
+    crc: u32,
+    next_chunk: Option<(u32, [u8; 4])>,
+    /// Copy of all data read so far, only kept if asked for
+    raw: Option<Vec<u8>>,
+    /// The error behind the last failed read, since Read can only return
+    /// std::io::Error
+    error: Option<PngError>,
+}
+
+impl<R: Read> IdatReader<R> {
+    /// Start reading the data of an IDAT chunk with the given length, after
+    /// its length and type have been read with read_chunk_header
+    fn new(reader: R, length: u32, crc_check: CrcCheck) -> IdatReader<R> {
+        IdatReader {
+            reader,
+            crc_check,
+            remaining: length,
+            crc: crc32_update(0xFFFFFFFF, b"IDAT"),
+            next_chunk: None,
+            raw: None,
+            error: None,
+        }
+    }
+
+    /// Verify the checksum of the current IDAT chunk and read the header of
+    /// the next chunk
+    fn next_idat(&mut self) -> Result<()> {
+        let mut crc = [0_u8; 4];
+        self.reader.read_exact(&mut crc).map_err(|_| {
+            PngError::WrongFormat("Chunk extends past the end of the file".to_string())
+        })?;
+        let running_crc = self.crc;
+        self.crc_check
+            .check("IDAT", u32::from_be_bytes(crc), || !running_crc)?;
+
+        match read_chunk_header(&mut self.reader)? {
+            (length, chunk_type) if &chunk_type == b"IDAT" => {
+                self.remaining = length;
+                self.crc = crc32_update(0xFFFFFFFF, b"IDAT");
+            }
+            next_chunk => self.next_chunk = Some(next_chunk),
+        }
+        Ok(())
+    }
+
+    /// Keep error so it can be taken back out and return an std::io::Error
+    fn fail(&mut self, error: PngError) -> std::io::Error {
+        let io_error = std::io::Error::new(std::io::ErrorKind::InvalidData, error.to_string());
+        self.error = Some(error);
+        io_error
+    }
+
+    /// Return the error behind a failed read, reads that did not fail because
+    /// of the chunks failed because of the compressed data itself
+    fn take_error(&mut self) -> PngError {
+        self.error.take().unwrap_or(PngError::DecompressionFailed)
+    }
+}
+
+impl<R: Read> Read for IdatReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.remaining == 0 {
+            if self.next_chunk.is_some() {
+                return Ok(0);
+            }
+            if let Err(error) = self.next_idat() {
+                return Err(self.fail(error));
+            }
+        }
+
+        let len = buf.len().min(self.remaining as usize);
+        let read = match self.reader.read(&mut buf[..len]) {
+            Ok(read) => read,
+            Err(_) => return Err(self.fail(PngError::CouldNotReadFile)),
+        };
+        if read == 0 && len > 0 {
+            return Err(self.fail(PngError::WrongFormat(
+                "Chunk extends past the end of the file".to_string(),
+            )));
+        }
+
+        self.crc = crc32_update(self.crc, &buf[..read]);
+        self.remaining -= read as u32;
+        if let Some(raw) = self.raw.as_mut() {
+            raw.extend_from_slice(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+/// Return the name of every chunk of the png image in the beginning of buf
+/// together with the range of bytes the chunk occupies in buf, including its
+/// length field and checksum
+pub(crate) fn chunk_layout(buf: &[u8]) -> Result<Vec<(String, std::ops::Range<usize>)>> {
+    check_if_png(buf)?;
+    let (chunks, _) = parse_chunks(buf, &mut CrcCheck::new(CrcPolicy::Fail))?;
+
+    let mut start = 8;
+    Ok(chunks
+        .iter()
+        .map(|chunk| {
+            let end = start + chunk.length as usize + 12;
+            let span = (chunk.chunk_type.name().to_string(), start..end);
+            start = end;
+            span
+        })
+        .collect())
+}
+
+/// List every chunk of the png image in the beginning of buf up to and
+/// including IEND without decompressing or reconstructing the image data. A
+/// chunk with a wrong checksum is listed with crc_ok set to false instead of
+/// failing, while a chunk that does not fit in buf is still an error
+pub fn list_chunks(buf: &[u8]) -> Result<Vec<ChunkInfo>> {
+    check_if_png(buf)?;
+    let (chunks, _) = parse_chunks(buf, &mut CrcCheck::new(CrcPolicy::Ignore))?;
+
+    let mut offset = 8;
+    Ok(chunks
+        .iter()
+        .map(|chunk| {
+            let crc_start = offset + 8 + chunk.length as usize;
+            let stored = u32::from_be_bytes(buf[crc_start..crc_start + 4].try_into().unwrap());
+            let info = ChunkInfo {
+                chunk_type: chunk.chunk_type.name().to_string(),
+                length: chunk.length,
+                crc_ok: crc32(&buf[offset + 4..crc_start]) == stored,
+                offset,
+            };
+            offset = crc_start + 4;
+            info
+        })
+        .collect())
+}
+
+/// Find a PLTE block among the chunks and parse the palette colors, if
+/// no PLTE block is present return None. Returns an error unless the palette
+/// holds between 1 and 256 complete RGB entries, so that palette lookups can
+/// only fail for indices in the image data
+fn parse_palette(chunks: &[Chunk]) -> Result<Option<Vec<Color>>> {
+    let chunk = match chunks
+        .iter()
+        .find(|chunk| matches!(chunk.chunk_type, ChunkType::Plte))
+    {
+        Some(chunk) => chunk,
+        None => return Ok(None),
+    };
+
+    let color_data = chunk.chunk_data.as_deref().unwrap_or_default();
+    if color_data.is_empty() || color_data.len() % 3 != 0 || color_data.len() > 256 * 3 {
+        return Err(PngError::WrongFormat(format!(
+            "Invalid PLTE chunk len {}",
+            color_data.len()
+        )));
+    }
+
+    let res = color_data
+        .chunks(3)
+        .map(|rgb| Color {
+            red: rgb[0],
+            green: rgb[1],
+            blue: rgb[2],
+            alpha: 255,
+        })
+        .collect();
+    Ok(Some(res))
+}
+
+/// Find a gAMA chunk among the chunks and return the gamma times 100000 as
+/// stored in the file, if no gAMA chunk is present return None. A gAMA chunk
+/// with the wrong length is ignored, strict mode rejects it before this point
+fn parse_gamma(chunks: &[Chunk]) -> Result<Option<u32>> {
+    let chunk = match chunks
+        .iter()
+        .find(|chunk| matches!(chunk.chunk_type, ChunkType::Gama))
+    {
+        Some(chunk) => chunk,
+        None => return Ok(None),
+    };
+
+    let data = chunk.chunk_data.as_deref().unwrap_or_default();
+    if data.len() != 4 {
+        return Ok(None);
+    }
+
+    match u32::from_be_bytes(data.try_into().unwrap()) {
+        0 => Err(PngError::WrongFormat("gAMA value is zero".to_string())),
+        gamma => Ok(Some(gamma)),
+    }
+}
+
+/// Find a sRGB chunk among the chunks and return its rendering intent, which
+/// is between 0 and 3, if no sRGB chunk is present return None
+fn parse_srgb(chunks: &[Chunk]) -> Result<Option<u8>> {
+    let chunk = match chunks
+        .iter()
+        .find(|chunk| matches!(chunk.chunk_type, ChunkType::Srgb))
+    {
+        Some(chunk) => chunk,
+        None => return Ok(None),
+    };
+
+    match chunk.chunk_data.as_deref().unwrap_or_default() {
+        [intent @ 0..=3] => Ok(Some(*intent)),
+        [_] => Err(PngError::WrongFormat(
+            "Invalid sRGB rendering intent".to_string(),
+        )),
+        _ => Err(PngError::WrongFormat("sRGB chunk len != 1".to_string())),
+    }
+}
+
+/// Find a tIME chunk among the chunks and return the time of the last
+/// modification as (year, month, day, hour, minute, second), if no tIME chunk
+/// is present return None. The second may be 60 to allow for leap seconds
+fn parse_time(chunks: &[Chunk]) -> Result<Option<Timestamp>> {
+    let chunk = match chunks
+        .iter()
+        .find(|chunk| matches!(chunk.chunk_type, ChunkType::Time))
+    {
+        Some(chunk) => chunk,
+        None => return Ok(None),
+    };
+
+    match chunk.chunk_data.as_deref().unwrap_or_default() {
+        &[y0, y1, month @ 1..=12, day @ 1..=31, hour @ 0..=23, minute @ 0..=59, second @ 0..=60] => {
+            Ok(Some((
+                u16::from_be_bytes([y0, y1]),
+                month,
+                day,
+                hour,
+                minute,
+                second,
+            )))
+        }
+        [_, _, _, _, _, _, _] => Err(PngError::WrongFormat("Invalid tIME value".to_string())),
+        _ => Err(PngError::WrongFormat("tIME chunk len != 7".to_string())),
+    }
+}
+
+/// Find a hIST chunk among the chunks and return the frequency of every
+/// palette entry, if no hIST chunk is present return None. The histogram must
+/// have exactly one entry per palette color
+fn parse_histogram(chunks: &[Chunk], palette: Option<&[Color]>) -> Result<Option<Vec<u16>>> {
+    let chunk = match chunks
+        .iter()
+        .find(|chunk| matches!(chunk.chunk_type, ChunkType::Hist))
+    {
+        Some(chunk) => chunk,
+        None => return Ok(None),
+    };
+
+    let palette = palette
+        .ok_or_else(|| PngError::WrongFormat("hIST chunk without a PLTE chunk".to_string()))?;
+    let data = chunk.chunk_data.as_deref().unwrap_or_default();
+    if data.len() != palette.len() * 2 {
+        return Err(PngError::WrongFormat(
+            "hIST chunk does not match the palette length".to_string(),
+        ));
+    }
+
+    Ok(Some(
+        data.chunks(2)
+            .map(|freq| u16::from_be_bytes([freq[0], freq[1]]))
+            .collect(),
+    ))
+}
+
+/// Find an iCCP chunk among the chunks and return the profile name and the
+/// decompressed ICC profile, if no iCCP chunk is present return None. The
+/// Latin-1 name of 1 to 79 bytes is followed by a null byte, a compression
+/// method byte, which must be 0, and the zlib compressed profile
+fn parse_icc_profile(chunks: &[Chunk]) -> Result<Option<(String, Vec<u8>)>> {
+    let chunk = match chunks
+        .iter()
+        .find(|chunk| matches!(chunk.chunk_type, ChunkType::Iccp))
+    {
+        Some(chunk) => chunk,
+        None => return Ok(None),
+    };
+
+    let data = chunk.chunk_data.as_deref().unwrap_or_default();
+    let separator = match data.iter().position(|&byte| byte == 0) {
+        Some(idx @ 1..=79) => idx,
+        _ => {
+            return Err(PngError::WrongFormat(
+                "iCCP profile name must be 1 to 79 bytes".to_string(),
+            ))
+        }
+    };
+    let profile = match &data[separator + 1..] {
+        [0, compressed @ ..] => decompress(compressed)?,
+        [method, ..] => return Err(PngError::UnsupportedCompression(*method)),
+        [] => {
+            return Err(PngError::WrongFormat(
+                "iCCP chunk has no compression method".to_string(),
+            ))
+        }
+    };
+    Ok(Some((latin1_to_string(&data[..separator]), profile)))
+}
+
+/// Find an oFFs chunk among the chunks and parse the image offset, if no oFFs
+/// chunk is present return None
+fn parse_offset(chunks: &[Chunk]) -> Result<Option<ImageOffset>> {
+    let chunk = match chunks
+        .iter()
+        .find(|chunk| matches!(chunk.chunk_type, ChunkType::Offs))
+    {
+        Some(chunk) => chunk,
+        None => return Ok(None),
+    };
+
+    let data = chunk.chunk_data.as_deref().unwrap_or_default();
+    if data.len() != 9 {
+        return Err(PngError::WrongFormat("oFFs chunk len != 9".to_string()));
+    }
+
+    let unit = match data[8] {
+        0 => Ok(OffsetUnit::Pixel),
+        1 => Ok(OffsetUnit::Micrometre),
+        _ => Err(PngError::WrongFormat("Invalid oFFs unit".to_string())),
+    }?;
+
+    Ok(Some(ImageOffset {
+        x: i32::from_be_bytes(data[0..4].try_into().unwrap()),
+        y: i32::from_be_bytes(data[4..8].try_into().unwrap()),
+        unit,
+    }))
+}
+
+/// Find a tRNS chunk among the chunks and return its contents, if no tRNS
+/// chunk is present return None. For indexed color images the alpha values are
+/// applied to the palette, entries beyond the end of the table stay opaque
+fn parse_transparency(
+    chunks: &[Chunk],
+    color_type: &ColorType,
+    palette: Option<&mut Vec<Color>>,
+) -> Result<Option<Vec<u8>>> {
+    let chunk = match chunks
+        .iter()
+        .find(|chunk| matches!(chunk.chunk_type, ChunkType::Trns))
+    {
+        Some(chunk) => chunk,
+        None => return Ok(None),
+    };
+    let data = chunk.chunk_data.clone().unwrap_or_default();
+
+    match (color_type, palette) {
+        (ColorType::IndexedColor, Some(palette)) => {
+            if data.len() > palette.len() {
+                return Err(PngError::WrongFormat(
+                    "tRNS chunk longer than the palette".to_string(),
+                ));
+            }
+            for (color, &alpha) in palette.iter_mut().zip(&data) {
+                color.alpha = alpha;
+            }
+        }
+        (ColorType::GrayScaleWithAlpha | ColorType::TrueColorWithAlpha, _) => {
+            return Err(PngError::WrongFormat(format!(
+                "tRNS chunk not allowed for {}",
+                color_type
+            )))
+        }
+        _ => {}
+    }
+    Ok(Some(data))
+}
+
+/// Find a pHYs chunk among the chunks and return the pixels per unit along
+/// the x and y axis and the unit, if no pHYs chunk is present return None
+fn parse_physical_dimensions(chunks: &[Chunk]) -> Result<Option<(u32, u32, u8)>> {
+    let chunk = match chunks
+        .iter()
+        .find(|chunk| matches!(chunk.chunk_type, ChunkType::Phys))
+    {
+        Some(chunk) => chunk,
+        None => return Ok(None),
+    };
+
+    let data = chunk.chunk_data.as_deref().unwrap_or_default();
+    if data.len() != 9 {
+        return Err(PngError::WrongFormat("pHYs chunk len != 9".to_string()));
+    }
+    if data[8] > 1 {
+        return Err(PngError::WrongFormat("Invalid pHYs unit".to_string()));
+    }
+
+    Ok(Some((
+        u32::from_be_bytes(data[0..4].try_into().unwrap()),
+        u32::from_be_bytes(data[4..8].try_into().unwrap()),
+        data[8],
+    )))
+}
+
+/// Find a bKGD chunk among the chunks and return the background color it
+/// suggests, if no bKGD chunk is present return None. Indexed color images
+/// store a palette index, other images a 16-bit gray or RGB sample value which
+/// is scaled to 8 bits according to the bit depth
+fn parse_background(
+    chunks: &[Chunk],
+    color_type: &ColorType,
+    bit_depth: u8,
+    palette: Option<&[Color]>,
+) -> Result<Option<Color>> {
+    let chunk = match chunks
+        .iter()
+        .find(|chunk| matches!(chunk.chunk_type, ChunkType::Bkgd))
+    {
+        Some(chunk) => chunk,
+        None => return Ok(None),
+    };
+    let data = chunk.chunk_data.as_deref().unwrap_or_default();
+
+    // 16-bit samples keep their most significant byte and samples below 8 bits
+    // are stretched over the full range
+    let max_value = ((1_u32 << bit_depth) - 1) as u16;
+    let to_8bit = |sample: &[u8]| {
+        let value = u16::from_be_bytes([sample[0], sample[1]]);
+        match bit_depth {
+            16 => Ok((value >> 8) as u8),
+            _ if value > max_value => Err(PngError::WrongFormat(
+                "bKGD sample too large for the bit depth".to_string(),
+            )),
+            _ => Ok((value as u32 * 255 / max_value as u32) as u8),
+        }
+    };
+
+    let (red, green, blue) = match (color_type, data.len()) {
+        (ColorType::IndexedColor, 1) => {
+            match palette.and_then(|palette| palette.get(data[0] as usize)) {
+                Some(color) => (color.red, color.green, color.blue),
+                None => {
+                    return Err(PngError::WrongFormat(
+                        "bKGD palette index out of range".to_string(),
+                    ))
+                }
+            }
+        }
+        (ColorType::Grayscale | ColorType::GrayScaleWithAlpha, 2) => {
+            let gray = to_8bit(data)?;
+            (gray, gray, gray)
+        }
+        (ColorType::Truecolor | ColorType::TrueColorWithAlpha, 6) => (
+            to_8bit(&data[0..2])?,
+            to_8bit(&data[2..4])?,
+            to_8bit(&data[4..6])?,
+        ),
+        _ => {
+            return Err(PngError::WrongFormat(format!(
+                "bKGD chunk len {} invalid for {}",
+                data.len(),
+                color_type
+            )))
+        }
+    };
+    Ok(Some(Color {
+        red,
+        green,
+        blue,
+        alpha: 255,
+    }))
+}
+
+/// Find a sBIT chunk among the chunks and return the number of significant
+/// bits of every sample of the color type, if no sBIT chunk is present return
+/// None. Every value must be between 1 and the sample depth, which is 8 for
+/// the palette colors of indexed images and the bit depth otherwise
+fn parse_significant_bits(
+    chunks: &[Chunk],
+    color_type: &ColorType,
+    bit_depth: u8,
+) -> Result<Option<Vec<u8>>> {
+    let chunk = match chunks
+        .iter()
+        .find(|chunk| matches!(chunk.chunk_type, ChunkType::Sbit))
+    {
+        Some(chunk) => chunk,
+        None => return Ok(None),
+    };
+    let data = chunk.chunk_data.as_deref().unwrap_or_default();
+
+    let (samples, sample_depth) = match color_type {
+        ColorType::Grayscale => (1, bit_depth),
+        ColorType::GrayScaleWithAlpha => (2, bit_depth),
+        ColorType::Truecolor => (3, bit_depth),
+        ColorType::IndexedColor => (3, 8),
+        ColorType::TrueColorWithAlpha => (4, bit_depth),
+    };
+    if data.len() != samples {
+        return Err(PngError::WrongFormat(format!(
+            "sBIT chunk len {} invalid for {}",
+            data.len(),
+            color_type
+        )));
+    }
+    if data.iter().any(|&bits| bits == 0 || bits > sample_depth) {
+        return Err(PngError::WrongFormat(
+            "sBIT value out of range for the bit depth".to_string(),
+        ));
+    }
+    Ok(Some(data.to_vec()))
+}
+
+/// Decode bytes as Latin-1, which maps every byte to the code point of the
+/// same value
+fn latin1_to_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| byte as char).collect()
+}
+
+/// Parse every tEXt, zTXt and iTXt chunk among the chunks, in the order they
+/// appear. All start with a Latin-1 keyword of 1 to 79 bytes followed by a
+/// null byte. In tEXt the rest is the Latin-1 text, in zTXt it is a
+/// compression method byte, which must be 0, and the zlib compressed text.
+/// The rest of iTXt is parsed by parse_international_text
+fn parse_text(chunks: &[Chunk]) -> Result<Vec<TextChunk>> {
+    chunks
+        .iter()
+        .filter(|chunk| {
+            matches!(
+                chunk.chunk_type,
+                ChunkType::Text | ChunkType::Ztxt | ChunkType::Itxt
+            )
+        })
+        .map(|chunk| {
+            let name = chunk.chunk_type.name();
+            let data = chunk.chunk_data.as_deref().unwrap_or_default();
+            let separator = match data.iter().position(|&byte| byte == 0) {
+                Some(idx) => idx,
+                None => {
+                    return Err(PngError::WrongFormat(format!(
+                        "{} chunk has no null separator",
+                        name
+                    )))
+                }
+            };
+            if !(1..=79).contains(&separator) {
+                return Err(PngError::WrongFormat(format!(
+                    "{} keyword must be 1 to 79 bytes",
+                    name
+                )));
+            }
+            let keyword = latin1_to_string(&data[..separator]);
+
+            if matches!(chunk.chunk_type, ChunkType::Itxt) {
+                let (language_tag, translated_keyword, text) =
+                    parse_international_text(&data[separator + 1..])?;
+                return Ok(TextChunk {
+                    keyword,
+                    text,
+                    language_tag: Some(language_tag),
+                    translated_keyword: Some(translated_keyword),
+                });
+            }
+
+            let text = match (&chunk.chunk_type, &data[separator + 1..]) {
+                (ChunkType::Ztxt, [0, compressed @ ..]) => decompress(compressed)?,
+                (ChunkType::Ztxt, [method, ..]) => {
+                    return Err(PngError::UnsupportedCompression(*method))
+                }
+                (ChunkType::Ztxt, []) => {
+                    return Err(PngError::WrongFormat(
+                        "zTXt chunk has no compression method".to_string(),
+                    ))
+                }
+                (_, text) => text.to_vec(),
+            };
+            Ok(TextChunk {
+                keyword,
+                text: latin1_to_string(&text),
+                language_tag: None,
+                translated_keyword: None,
+            })
+        })
+        .collect()
+}
+
+/// Parse the part of an iTXt chunk after the keyword and return the language
+/// tag, the translated keyword and the text. It holds a compression flag, a
+/// compression method, the null terminated language tag and translated
+/// keyword and finally the UTF-8 text, which is zlib compressed if the flag
+/// is 1. Text that is not valid UTF-8 is an error
+fn parse_international_text(data: &[u8]) -> Result<(String, String, String)> {
+    let (compression_flag, compression_method, rest) = match data {
+        [flag, method, rest @ ..] => (*flag, *method, rest),
+        _ => {
+            return Err(PngError::WrongFormat(
+                "iTXt chunk has no compression flag".to_string(),
+            ))
+        }
+    };
+
+    let mut fields = rest.splitn(3, |&byte| byte == 0);
+    let (language_tag, translated_keyword, text) =
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some(language_tag), Some(translated_keyword), Some(text)) => {
+                (language_tag, translated_keyword, text)
+            }
+            _ => {
+                return Err(PngError::WrongFormat(
+                    "iTXt chunk has no null separator".to_string(),
+                ))
+            }
+        };
+
+    let text = match (compression_flag, compression_method) {
+        (0, _) => text.to_vec(),
+        (1, 0) => decompress(text)?,
+        (1, method) => return Err(PngError::UnsupportedCompression(method)),
+        (flag, _) => {
+            return Err(PngError::WrongFormat(format!(
+                "Invalid iTXt compression flag {}",
+                flag
+            )))
+        }
+    };
+
+    let to_utf8 = |bytes: Vec<u8>, field: &str| {
+        String::from_utf8(bytes)
+            .map_err(|_| PngError::WrongFormat(format!("iTXt {} is not valid UTF-8", field)))
+    };
+    Ok((
+        to_utf8(language_tag.to_vec(), "language tag")?,
+        to_utf8(translated_keyword.to_vec(), "translated keyword")?,
+        to_utf8(text, "text")?,
+    ))
+}
+
+/// Find an acTL chunk among the chunks and return the number of frames and the
+/// number of plays of the animation, if no acTL chunk is present return None
+fn parse_animation_control(chunks: &[Chunk]) -> Result<Option<(u32, u32)>> {
+    let chunk = match chunks
+        .iter()
+        .find(|chunk| matches!(chunk.chunk_type, ChunkType::Actl))
+    {
+        Some(chunk) => chunk,
+        None => return Ok(None),
+    };
+
+    let data = chunk.chunk_data.as_deref().unwrap_or_default();
+    if data.len() != 8 {
+        return Err(PngError::WrongFormat("acTL chunk len != 8".to_string()));
+    }
+    let frames = u32::from_be_bytes(data[0..4].try_into().unwrap());
+    if frames == 0 {
+        return Err(PngError::WrongFormat("acTL frame count is 0".to_string()));
+    }
+
+    Ok(Some((
+        frames,
+        u32::from_be_bytes(data[4..8].try_into().unwrap()),
+    )))
+}
+
+/// Parse every fcTL chunk among the chunks into a Frame
+fn parse_frames(chunks: &[Chunk]) -> Result<Vec<Frame>> {
+    chunks
+        .iter()
+        .filter(|chunk| matches!(chunk.chunk_type, ChunkType::Fctl))
+        .map(|chunk| {
+            let data = chunk.chunk_data.as_deref().unwrap_or_default();
+            if data.len() != 26 {
+                return Err(PngError::WrongFormat("fcTL chunk len != 26".to_string()));
+            }
+
+            let read_u32 = |idx: usize| u32::from_be_bytes(data[idx..idx + 4].try_into().unwrap());
+            let read_u16 = |idx: usize| u16::from_be_bytes(data[idx..idx + 2].try_into().unwrap());
+            Ok(Frame {
+                sequence_number: read_u32(0),
+                width: read_u32(4),
+                height: read_u32(8),
+                x_offset: read_u32(12),
+                y_offset: read_u32(16),
+                delay_num: read_u16(20),
+                delay_den: read_u16(22),
+                dispose_op: data[24],
+                blend_op: data[25],
+            })
+        })
+        .collect()
+}
+
+/// Check that a sCAL measurement is an ASCII floating-point number greater
+/// than zero and return it as a String
+fn parse_scale_value(value: &[u8]) -> Result<String> {
+    let invalid = || PngError::WrongFormat("Invalid sCAL measurement".to_string());
+
+    // The spec does not allow a sign, which also keeps "inf" and "nan" out
+    if !value
+        .first()
+        .is_some_and(|b| b.is_ascii_digit() || *b == b'.')
+    {
+        return Err(invalid());
+    }
+    let text = std::str::from_utf8(value).map_err(|_| invalid())?;
+    match text.parse::<f64>() {
+        Ok(number) if number > 0.0 && number.is_finite() => Ok(text.to_string()),
+        _ => Err(invalid()),
+    }
+}
+
+/// Find a sCAL chunk among the chunks and parse the physical scale, if no sCAL
+/// chunk is present return None
+fn parse_scale(chunks: &[Chunk]) -> Result<Option<PhysicalScale>> {
+    let chunk = match chunks
+        .iter()
+        .find(|chunk| matches!(chunk.chunk_type, ChunkType::Scal))
+    {
+        Some(chunk) => chunk,
+        None => return Ok(None),
+    };
+
+    let data = chunk.chunk_data.as_deref().unwrap_or_default();
+    if data.is_empty() {
+        return Err(PngError::WrongFormat("sCAL chunk is empty".to_string()));
+    }
+
+    let unit = match data[0] {
+        1 => Ok(ScaleUnit::Metre),
+        2 => Ok(ScaleUnit::Radian),
+        _ => Err(PngError::WrongFormat("Invalid sCAL unit".to_string())),
+    }?;
+
+    // Width and height are separated by a single null byte
+    let mut values = data[1..].split(|&b| b == 0);
+    let (width, height) = match (values.next(), values.next(), values.next()) {
+        (Some(width), Some(height), None) => (width, height),
+        _ => {
+            return Err(PngError::WrongFormat(
+                "sCAL chunk must contain a width and a height".to_string(),
+            ))
+        }
+    };
+
+    Ok(Some(PhysicalScale {
+        unit,
+        width: parse_scale_value(width)?,
+        height: parse_scale_value(height)?,
+    }))
+}
+
+/// Return the names of the ancillary chunks in the order they first appear
+fn collect_ancillary_names(chunks: &[Chunk]) -> Vec<String> {
+    let mut res: Vec<String> = Vec::new();
+
+    for chunk in chunks {
+        let is_critical = matches!(
+            chunk.chunk_type,
+            ChunkType::Ihrd | ChunkType::Plte | ChunkType::Idat | ChunkType::Iend
+        );
+        let name = chunk.chunk_type.name();
+        if !is_critical && !res.iter().any(|seen| seen == name) {
+            res.push(name.to_string());
+        }
+    }
+    res
+}
+
+/// Go over all IDAT blocks among the chunks and concatenate all the blocks
+/// into a single Vec<u8>
+fn collect_idat_data(chunks: Vec<Chunk>) -> Vec<u8> {
+    let mut res: Vec<u8> = Vec::new();
+
+    for chunk in chunks {
+        // Empty IDAT chunks are allowed and carry no data
+        if let (ChunkType::Idat, Some(mut data)) = (chunk.chunk_type, chunk.chunk_data) {
+            res.append(&mut data);
+        }
+    }
+    res
+}
+
+/// Check the LZ77 window size given by the first byte of a zlib stream, since
+/// png limits it to 32768 bytes
+fn check_zlib_window(cmf: u8) -> Result<()> {
+    // The upper nibble of the first byte (CINFO) is the base-2 logarithm of
+    // the window size minus 8
+    if cmf >> 4 > 7 {
+        return Err(PngError::WrongFormat("zlib window too large".to_string()));
+    }
+    Ok(())
+}
+
+/// Return a reader decompressing the zlib stream in data. The LZ77 window
+/// size in the zlib header is checked first
+pub(crate) fn zlib_reader(data: &[u8]) -> Result<flate2::read::ZlibDecoder<&[u8]>> {
+    if let Some(&cmf) = data.first() {
+        check_zlib_window(cmf)?;
+    }
+    Ok(flate2::read::ZlibDecoder::new(data))
+}
+
+/// Decompress data and return it
+fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut decompressed: Vec<u8> = Vec::new();
+    match zlib_reader(data)?.read_to_end(&mut decompressed) {
+        Ok(_) => Ok(decompressed),
+        Err(_) => Err(PngError::DecompressionFailed),
+    }
+}
+
+/// Return the number of bits used by a single pixel
+pub(crate) fn bits_per_pixel(bit_depth: u8, color_type: &ColorType) -> usize {
+    color_type.channels() * bit_depth as usize
+}
+
+/// Calculate and return the number of bytes needed to contain a scanline excluding
+/// the filter byte. Also return the offset (bytes) to the previous pixel in the
+/// scanline, which is the number of bytes per pixel rounded up to at least 1.
+/// Returns an error if the bit depth is not allowed for the color type
+pub(crate) fn calc_bytes_per_scanline_and_filt_offset(
+    width: u32,
+    bit_depth: u8,
+    color_type: &ColorType,
+) -> Result<(usize, usize)> {
+    check_bit_depth(bit_depth, color_type)?;
+    let bits_per_pixel = bits_per_pixel(bit_depth, color_type);
+
+    // Sub-byte samples are packed, the last byte of a scanline may be
+    // partially used
+    Ok((
+        (width as usize * bits_per_pixel).div_ceil(8),
+        bits_per_pixel.div_ceil(8),
+    ))
+}
+
+/// Return the result of a Paeth predictor with values a,b and c
+fn paeth_predictor(a: i32, b: i32, c: i32) -> i32 {
+    let p = a.wrapping_add(b).wrapping_sub(c);
+    let pa = p.abs_diff(a);
+    let pb = p.abs_diff(b);
+    let pc = p.abs_diff(c);
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Reconstruct a byte filtered with filter type 0 (None), which stores the
+/// byte as it is
+fn apply_none(x: u8) -> u8 {
+    x
+}
+
+/// Reconstruct a byte filtered with filter type 1 (Sub) from the filtered
+/// byte x and the reconstructed byte a to its left
+fn apply_sub(x: u8, a: u8) -> u8 {
+    x.wrapping_add(a)
+}
+
+/// Reconstruct a byte filtered with filter type 2 (Up) from the filtered byte
+/// x and the reconstructed byte b above it
+fn apply_up(x: u8, b: u8) -> u8 {
+    x.wrapping_add(b)
+}
+
+/// Reconstruct a byte filtered with filter type 3 (Average) from the filtered
+/// byte x and the mean of a and b. The sum of a and b is taken without
+/// overflow and the division floors, as the specification requires
+fn apply_average(x: u8, a: u8, b: u8) -> u8 {
+    x.wrapping_add(((a as u16 + b as u16) / 2) as u8)
+}
+
+/// Reconstruct a byte filtered with filter type 4 (Paeth) from the filtered
+/// byte x and the byte among a, b and c chosen by the Paeth predictor
+fn apply_paeth(x: u8, a: u8, b: u8, c: u8) -> u8 {
+    x.wrapping_add(paeth_predictor(a as i32, b as i32, c as i32) as u8)
+}
+
+/// Return the value of the A byte according to the png specification The A byte is
+/// defined as the corresponding byte of the pixel to the left, offset bytes
+/// back in the scanline. If we are in the first pixel of a scanline the A byte
+/// is 0
+fn get_a(offset: usize, byte_idx: usize, scanline: &[u8]) -> u8 {
+    if byte_idx >= offset {
+        scanline[byte_idx - offset]
+    } else {
+        0
+    }
+}
+/// Return the value of the B byte according to the png specification The B byte is
+/// defined as the byte "above" the current byte in the scanline, ie the byte at
+/// the same position in the scanline from the previous scanline If we are
+/// on the first scanline there will be no scanline above and B will be 0
+fn get_b(byte_idx: usize, prev_scanline: Option<&[u8]>) -> u8 {
+    match prev_scanline {
+        Some(prev) => prev[byte_idx],
+        None => 0,
+    }
+}
+/// Return the value of the C byte according to the png specification The C byte is
+/// defined as the byte offset bytes to the left of the B byte. If we are on the
+/// first scanline or in the first pixel of a scanline C will be 0
+fn get_c(offset: usize, byte_idx: usize, prev_scanline: Option<&[u8]>) -> u8 {
+    match prev_scanline {
+        Some(prev) if byte_idx >= offset => prev[byte_idx - offset],
+        _ => 0,
+    }
+}
+
+/// Perform reconstruction on a single scanline, starting with its filter byte,
+/// and return the reconstructed bytes. prev_scanline is the previous
+/// reconstructed scanline, or None for the first scanline of the image.
+/// Filter types above 4 are passed on to custom_filter if given
+pub(crate) fn reconstruct_scanline(
+    filtered: &[u8],
+    prev_scanline: Option<&[u8]>,
+    offset: usize,
+    custom_filter: Option<&CustomFilter>,
+) -> Result<Vec<u8>> {
+    let filter_type = filtered[0];
+    let mut res: Vec<u8> = Vec::with_capacity(filtered.len() - 1);
+
+    for (byte_idx, &x) in filtered[1..].iter().enumerate() {
+        // A bit unessecary to get these each iteration regardless of
+        // filter type but it looks a little cleaner code-wise
+        let a = get_a(offset, byte_idx, &res);
+        let b = get_b(byte_idx, prev_scanline);
+        let c = get_c(offset, byte_idx, prev_scanline);
+
+        let filt_x = match filter_type {
+            0 => apply_none(x),
+            1 => apply_sub(x, a),
+            2 => apply_up(x, b),
+            3 => apply_average(x, a, b),
+            4 => apply_paeth(x, a, b, c),
+            _ => match custom_filter {
+                Some(filter) => {
+                    let filt_x = filter(filter_type, x as i32, a as i32, b as i32, c as i32);
+                    (filt_x & 0xFF) as u8
+                }
+                None => return Err(PngError::FilterNotSupported(filter_type)),
+            },
+        };
+        res.push(filt_x);
+    }
+
+    Ok(res)
+}
+
+/// Perform reconstruction on the png image data and return a vector containing
+/// the decoded data
+fn reconstruct(
+    data: &[u8],
+    height: u32,
+    bytes_per_scanline: usize,
+    offset: usize,
+    custom_filter: Option<&CustomFilter>,
+) -> Result<Vec<u8>> {
+    let needed = (bytes_per_scanline + 1).checked_mul(height as usize);
+    if needed.is_none_or(|needed| data.len() < needed) {
+        return Err(PngError::TruncatedData);
+    }
+    let mut res: Vec<u8> = Vec::new();
+
+    for scanline_idx in 0..height as usize {
+        // Every scanline is preceded by its filter byte
+        let start = scanline_idx * (bytes_per_scanline + 1);
+        let filtered = &data[start..start + bytes_per_scanline + 1];
+
+        let prev_scanline = match scanline_idx {
+            0 => None,
+            _ => Some(&res[(scanline_idx - 1) * bytes_per_scanline..]),
+        };
+        let scanline = reconstruct_scanline(filtered, prev_scanline, offset, custom_filter)?;
+        res.extend(scanline);
+    }
+
+    Ok(res)
+}
+
+/// Starting column, starting row, column step and row step of each of the
+/// seven Adam7 passes
+const ADAM7_PASSES: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Perform reconstruction on Adam7 interlaced image data and return the
+/// reconstructed data laid out as for a non-interlaced image. Every pass is a
+/// separate sub-image with its own scanlines and filter bytes, passes that
+/// contain no pixels are left out of the data entirely. Returns NotSupported
+/// if the crate is built without the adam7 feature
+fn reconstruct_interlaced(
+    data: &[u8],
+    png_image: &PngImage,
+    custom_filter: Option<&CustomFilter>,
+) -> Result<Vec<u8>> {
+    if !cfg!(feature = "adam7") {
+        return Err(PngError::NotSupported(
+            "Adam7 interlaced images without the adam7 feature".to_string(),
+        ));
+    }
+    let (width, height) = (png_image.width as usize, png_image.height as usize);
+    let bits_per_pixel = bits_per_pixel(png_image.bit_depth, &png_image.color_type);
+    let (bytes_per_scanline, _) = calc_bytes_per_scanline_and_filt_offset(
+        png_image.width,
+        png_image.bit_depth,
+        &png_image.color_type,
+    )?;
+    let filt_offset = png_image.bytes_per_pixel();
+
+    // Check the data covers every pass before allocating the output, since a
+    // corrupt header can claim far more pixels than the file holds
+    let mut needed = 0;
+    for (x0, y0, dx, dy) in ADAM7_PASSES {
+        let pass_width = width.saturating_sub(x0).div_ceil(dx);
+        let pass_height = height.saturating_sub(y0).div_ceil(dy);
+        if pass_width > 0 && pass_height > 0 {
+            let (pass_bytes_per_scanline, _) = calc_bytes_per_scanline_and_filt_offset(
+                pass_width as u32,
+                png_image.bit_depth,
+                &png_image.color_type,
+            )?;
+            needed += pass_height * (pass_bytes_per_scanline + 1);
+        }
+    }
+    if data.len() < needed {
+        return Err(PngError::TruncatedData);
+    }
+
+    let mut res: Vec<u8> = vec![0; bytes_per_scanline * height];
+    let mut pass_start = 0;
+
+    for (x0, y0, dx, dy) in ADAM7_PASSES {
+        let pass_width = width.saturating_sub(x0).div_ceil(dx);
+        let pass_height = height.saturating_sub(y0).div_ceil(dy);
+        if pass_width == 0 || pass_height == 0 {
+            continue;
+        }
+
+        let (pass_bytes_per_scanline, _) = calc_bytes_per_scanline_and_filt_offset(
+            pass_width as u32,
+            png_image.bit_depth,
+            &png_image.color_type,
+        )?;
+        let pass_end = pass_start + pass_height * (pass_bytes_per_scanline + 1);
+        let pass_data = data
+            .get(pass_start..pass_end)
+            .ok_or(PngError::TruncatedData)?;
+        let pass = reconstruct(
+            pass_data,
+            pass_height as u32,
+            pass_bytes_per_scanline,
+            filt_offset,
+            custom_filter,
+        )?;
+        pass_start = pass_end;
+
+        // Move every pixel of the pass to its place in the full image
+        for py in 0..pass_height {
+            let pass_row = &pass[py * pass_bytes_per_scanline..];
+            let row = &mut res[(y0 + py * dy) * bytes_per_scanline..];
+
+            for px in 0..pass_width {
+                let src_bit = px * bits_per_pixel;
+                let dst_bit = (x0 + px * dx) * bits_per_pixel;
+
+                if bits_per_pixel >= 8 {
+                    let len = bits_per_pixel / 8;
+                    row[dst_bit / 8..dst_bit / 8 + len]
+                        .copy_from_slice(&pass_row[src_bit / 8..src_bit / 8 + len]);
+                } else {
+                    let mask = ((1_u16 << bits_per_pixel) - 1) as u8;
+                    let value = pass_row[src_bit / 8] >> (8 - bits_per_pixel - src_bit % 8) & mask;
+                    row[dst_bit / 8] |= value << (8 - bits_per_pixel - dst_bit % 8);
+                }
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+/// Scale big-endian 16-bit samples down to 8-bit samples by keeping their
+/// most significant byte, the same conversion the decoder uses for 16-bit
+/// images
+fn reduce_samples_to_8bit(data: &[u8]) -> Vec<u8> {
+    data.chunks(2).map(|sample| sample[0]).collect()
+}
+
+/// Fields of an IHDR chunk
+struct Header {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: ColorType,
+    compression_method: CompressionMethod,
+    filter_method: FilterMethod,
+    interlace_method: InterlaceMethod,
+}
+
+/// Return the bit depths the png specification allows for the color type
+pub(crate) fn allowed_bit_depths(color_type: &ColorType) -> &'static [u8] {
+    match color_type {
+        ColorType::Grayscale => &[1, 2, 4, 8, 16],
+        ColorType::IndexedColor => &[1, 2, 4, 8],
+        _ => &[8, 16],
+    }
+}
+
+/// Return an error naming the combination if the png specification does not
+/// allow the bit depth for the color type
+fn check_bit_depth(bit_depth: u8, color_type: &ColorType) -> Result<()> {
+    if !allowed_bit_depths(color_type).contains(&bit_depth) {
+        return Err(PngError::WrongFormat(format!(
+            "Bit depth {} not allowed for {}",
+            bit_depth, color_type
+        )));
+    }
+    Ok(())
+}
+
+/// Parse the metadata of an IHDR chunk, return an error if the chunk is not
+/// an IHDR or if any of its fields are invalid
+fn parse_header(chunk: &Chunk) -> Result<Header> {
+    if !matches!(chunk.chunk_type, ChunkType::Ihrd) {
+        return Err(PngError::WrongFormat(
+            "First chunk type != IHDR".to_string(),
+        ));
+    }
+    let ihdr_data = match &chunk.chunk_data {
+        Some(data) if data.len() == 13 => data,
+        Some(_) => return Err(PngError::WrongFormat("IHDR chunk len != 13".to_string())),
+        None => {
+            return Err(PngError::WrongFormat(
+                "IHDR chunk has no chunk data".to_string(),
+            ))
+        }
+    };
+
+    let width = u32::from_be_bytes(ihdr_data[0..4].try_into().unwrap());
+    let height = u32::from_be_bytes(ihdr_data[4..8].try_into().unwrap());
+    if width == 0 || height == 0 {
+        return Err(PngError::WrongFormat(
+            "Image has a zero dimension".to_string(),
+        ));
+    }
+
+    let color_type = match ihdr_data[9] {
+        0 => Ok(ColorType::Grayscale),
+        2 => Ok(ColorType::Truecolor),
+        3 => Ok(ColorType::IndexedColor),
+        4 => Ok(ColorType::GrayScaleWithAlpha),
+        6 => Ok(ColorType::TrueColorWithAlpha),
+        _ => Err(PngError::WrongFormat("Invalid color type".to_string())),
+    }?;
+
+    let bit_depth = ihdr_data[8];
+    check_bit_depth(bit_depth, &color_type)?;
+
+    let compression_method = match ihdr_data[10] {
+        0 => Ok(CompressionMethod::DeflateInflate),
+        other => Err(PngError::UnsupportedCompression(other)),
+    }?;
+
+    let filter_method = match ihdr_data[11] {
+        0 => Ok(FilterMethod::FiveTypeAdaptive),
+        _ => Err(PngError::WrongFormat("Invalid filter method".to_string())),
+    }?;
+
+    let interlace_method = match ihdr_data[12] {
+        0 => Ok(InterlaceMethod::NoInterlace),
+        1 => Ok(InterlaceMethod::Adam7Interlace),
+        _ => Err(PngError::WrongFormat(
+            "Invalid interlace method".to_string(),
+        )),
+    }?;
+
+    Ok(Header {
+        width,
+        height,
+        bit_depth,
+        color_type,
+        compression_method,
+        filter_method,
+        interlace_method,
+    })
+}
+
+#[derive(Debug)]
+/// Image metadata taken from the IHDR chunk alone
+pub struct PngInfo {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub interlaced: bool,
+}
+
+/// Read the metadata of a png image from the IHDR chunk in the beginning of
+/// buf. Only the signature and the IHDR chunk have to be intact, anything
+/// after it may be malformed or missing, which makes it possible to salvage
+/// the dimensions and color type of a truncated file. Decoding such a file
+/// still fails
+pub fn read_metadata(buf: &[u8]) -> Result<PngInfo> {
+    check_if_png(buf)?;
+
+    let mut crc_check = CrcCheck::new(CrcPolicy::Fail);
+    let header = parse_header(&Chunk::from_buffer_index(8, buf, &mut crc_check)?)?;
+    Ok(PngInfo {
+        width: header.width,
+        height: header.height,
+        bit_depth: header.bit_depth,
+        color_type: header.color_type,
+        interlaced: matches!(header.interlace_method, InterlaceMethod::Adam7Interlace),
+    })
+}
+
+/// Read the metadata of the png file pointed to by path like read_metadata,
+/// but only read the signature and the IHDR chunk from disk instead of the
+/// whole file
+pub fn read_png_info(path: &String) -> Result<PngInfo> {
+    // Signature (8) + IHDR length, type, data and checksum (4 + 4 + 13 + 4)
+    const HEADER_LEN: u64 = 33;
+
+    let f = std::fs::File::open(path).map_err(|_| PngError::CouldNotReadFile)?;
+    let mut buffer: Vec<u8> = Vec::new();
+    f.take(HEADER_LEN)
+        .read_to_end(&mut buffer)
+        .map_err(|_| PngError::CouldNotReadFile)?;
+
+    read_metadata(&buffer)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Optional features of the crate a png file can require
+pub enum Capability {
+    /// De-interlacing of Adam7 interlaced images, enabled by the adam7 feature
+    Adam7Interlacing,
+}
+
+/// Return the capabilities needed to decode the png image in the beginning of
+/// bytes that the current build of this crate lacks. An empty result means the
+/// image can be decoded, as far as the IHDR chunk tells
+pub fn missing_capabilities(bytes: &[u8]) -> Result<Vec<Capability>> {
+    let info = read_metadata(bytes)?;
+    let mut res: Vec<Capability> = Vec::new();
+
+    if info.interlaced && !cfg!(feature = "adam7") {
+        res.push(Capability::Adam7Interlacing);
+    }
+    Ok(res)
+}
+
+/// Parse the contents of a .png file pointed to by path and return a PngImage
+/// struct containing the parsed png image. Note that this does not include
+/// conversion from scanlines to actual RGB values, only decompression and
+/// reconstruction
+pub fn parse_png(path: &String) -> Result<PngImage> {
+    parse_png_with_options(path, &ParseOptions::default())
+}
+
+/// Same as parse_png but parses a png file already in memory instead of
+/// reading it from disk
+pub fn parse_png_bytes(buf: &[u8]) -> Result<PngImage> {
+    parse_png_bytes_with_options(buf, &ParseOptions::default())
+}
+
+/// Same as parse_png but reads the png file from any reader, such as a socket,
+/// a decompressing reader or a Cursor, see parse_png_from_reader
+pub fn parse_png_reader<R: Read>(reader: R) -> Result<PngImage> {
+    parse_png_from_reader(reader, &ParseOptions::default())
+}
+
+/// Same as parse_png but with control over the parsing through options
+pub fn parse_png_with_options(path: &String, options: &ParseOptions) -> Result<PngImage> {
+    let f = std::fs::File::open(path).map_err(|_| PngError::CouldNotReadFile)?;
+    parse_png_from_reader(std::io::BufReader::new(f), options)
+}
+
+/// Same as parse_png_with_options but reads the png file from reader. The file
+/// is never held in memory as a whole: chunks are read one at a time and the
+/// image data is decompressed and reconstructed scanline by scanline while it
+/// is read, so apart from the resulting image only a couple of scanlines are
+/// buffered. Interlaced images are decompressed as a whole before they are
+/// reconstructed. As required by the png specification the IDAT chunks have
+/// to be consecutive
+pub fn parse_png_from_reader<R: Read>(mut reader: R, options: &ParseOptions) -> Result<PngImage> {
+    let mut signature = [0_u8; 8];
+    reader
+        .read_exact(&mut signature)
+        .map_err(|_| PngError::NotAPng)?;
+    check_if_png(&signature)?;
+    let mut crc_check = CrcCheck::new(options.on_crc_error);
+
+    // The header is needed to reconstruct the image data, so every chunk up
+    // to the first IDAT is read before the data
+    let mut chunks: Vec<Chunk> = Vec::new();
+    let idat_length = loop {
+        let (length, chunk_type) = read_chunk_header(&mut reader)?;
+        if &chunk_type == b"IDAT" {
+            break length;
+        }
+        let chunk = read_chunk_body(&mut reader, length, chunk_type, &mut crc_check)?;
+        if matches!(chunk.chunk_type, ChunkType::Iend) {
+            return Err(PngError::WrongFormat("Missing IDAT chunk".to_string()));
+        }
+        chunks.push(chunk);
+    };
+    let header = header_from_chunks(&mut chunks, options)?;
+
+    // Stands in for the IDAT chunks, whose data is never collected, so that
+    // the order of the chunks can still be checked
+    chunks.push(Chunk {
+        length: idat_length,
+        chunk_type: ChunkType::Idat,
+        chunk_data: None,
+    });
+
+    // Problems with the chunks are reported before problems with the image
+    // data, like when parsing a buffer, since corrupt chunks tend to make
+    // the data fail to decompress. So all chunks are read before the result
+    // of decompressing is looked at
+    let mut idat = IdatReader::new(reader, idat_length, crc_check);
+    if options.keep_raw_idat {
+        idat.raw = Some(Vec::new());
+    }
+    let data = inflate_image_data(&mut idat, &header, options);
+    if let Some(error) = idat.error.take() {
+        return Err(error);
+    }
+    // Any IDAT data left after the end of the zlib stream is skipped
+    std::io::copy(&mut idat, &mut std::io::sink()).map_err(|_| idat.take_error())?;
+
+    let IdatReader {
+        mut reader,
+        mut next_chunk,
+        mut crc_check,
+        raw,
+        ..
+    } = idat;
+    loop {
+        let (length, chunk_type) = match next_chunk.take() {
+            Some(next_chunk) => next_chunk,
+            None => read_chunk_header(&mut reader)?,
+        };
+        if &chunk_type == b"IDAT" {
+            return Err(PngError::WrongFormat(
+                "IDAT chunks are not consecutive".to_string(),
+            ));
+        }
+        let chunk = read_chunk_body(&mut reader, length, chunk_type, &mut crc_check)?;
+        let is_iend = matches!(chunk.chunk_type, ChunkType::Iend);
+        chunks.push(chunk);
+        if is_iend {
+            break;
+        }
+    }
+
+    check_image_data_chunks(&chunks)?;
+
+    let interlaced = matches!(header.interlace_method, InterlaceMethod::Adam7Interlace);
+    let mut png_image = image_from_chunks(&chunks, header, options)?;
+    png_image.crc_mismatches = crc_check.mismatches;
+    png_image.raw_idat = raw;
+    let data = data?;
+
+    png_image.data = match interlaced {
+        true => reconstruct_interlaced(&data, &png_image, options.custom_filter.as_deref())?,
+        false => data,
+    };
+    if options.reduce_to_8bit && png_image.bit_depth == 16 {
+        // Non-interlaced data is already reduced scanline by scanline
+        if interlaced {
+            png_image.data = reduce_samples_to_8bit(&png_image.data);
+        }
+        png_image.reduced_to_8bit();
+    }
+    Ok(png_image)
+}
+
+/// Decompress the zlib stream read from source and return the reconstructed
+/// image data, keeping only the previous scanline besides the result. The
+/// data of interlaced images is returned decompressed but not reconstructed.
+/// 16-bit samples are reduced to 8 bits right away if options ask for it
+fn inflate_image_data<R: Read>(
+    mut source: R,
+    header: &Header,
+    options: &ParseOptions,
+) -> Result<Vec<u8>> {
+    let mut cmf = [0_u8; 1];
+    source
+        .read_exact(&mut cmf)
+        .map_err(|_| PngError::DecompressionFailed)?;
+    check_zlib_window(cmf[0])?;
+    let mut decoder = flate2::read::ZlibDecoder::new(cmf.as_slice().chain(source));
+
+    if matches!(header.interlace_method, InterlaceMethod::Adam7Interlace) {
+        let mut decompressed: Vec<u8> = Vec::new();
+        decoder
+            .read_to_end(&mut decompressed)
+            .map_err(|_| PngError::DecompressionFailed)?;
+        return Ok(decompressed);
+    }
+
+    let (bytes_per_scanline, filt_offset) = calc_bytes_per_scanline_and_filt_offset(
+        header.width,
+        header.bit_depth,
+        &header.color_type,
+    )?;
+    let reduce = options.reduce_to_8bit && header.bit_depth == 16;
+
+    // Allocate the result up front if possible. A header claiming more than
+    // fits in memory is not an error by itself, as when parsing a buffer the
+    // data ends before the last scanline unless the file really is that large
+    let mut res: Vec<u8> = Vec::new();
+    let res_len =
+        (bytes_per_scanline / if reduce { 2 } else { 1 }).saturating_mul(header.height as usize);
+    let _ = res.try_reserve_exact(res_len);
+
+    let mut filtered: Vec<u8> = vec![0; bytes_per_scanline + 1];
+    let mut prev_scanline: Option<Vec<u8>> = None;
+
+    for _ in 0..header.height {
+        decoder
+            .read_exact(&mut filtered)
+            .map_err(|error| match error.kind() {
+                std::io::ErrorKind::UnexpectedEof => PngError::TruncatedData,
+                _ => PngError::DecompressionFailed,
+            })?;
+        let scanline = reconstruct_scanline(
+            &filtered,
+            prev_scanline.as_deref(),
+            filt_offset,
+            options.custom_filter.as_deref(),
+        )?;
+        if reduce {
+            res.extend(reduce_samples_to_8bit(&scanline));
+        } else {
+            res.extend_from_slice(&scanline);
+        }
+        prev_scanline = Some(scanline);
+    }
+
+    // Read the rest of the stream so that its checksum is verified
+    std::io::copy(&mut decoder, &mut std::io::sink()).map_err(|_| PngError::DecompressionFailed)?;
+    Ok(res)
+}
+
+/// Same as parse_png_bytes but with control over the parsing through options
+pub fn parse_png_bytes_with_options(buf: &[u8], options: &ParseOptions) -> Result<PngImage> {
+    let (png_image, _) = parse_buffer(buf, options)?;
+    Ok(png_image)
+}
+
+/// Parse a png image contained in the beginning of buf and return it together
+/// with the bytes following its IEND chunk. The trailing bytes are empty for a
+/// well-formed file but can contain another png when several files have been
+/// concatenated
+pub fn parse_png_with_trailing(buf: &[u8]) -> Result<(PngImage, &[u8])> {
+    parse_buffer(buf, &ParseOptions::default())
+}
+
+/// Parse a png image contained in the beginning of buf according to options
+/// and return it together with the bytes following its IEND chunk
+fn parse_buffer<'a>(buf: &'a [u8], options: &ParseOptions) -> Result<(PngImage, &'a [u8])> {
+    let (mut png_image, idat_data, trailing) = parse_compressed(buf, options)?;
+    let decompressed = decompress(&idat_data)?;
+    if options.keep_raw_idat {
+        png_image.raw_idat = Some(idat_data);
+    }
+
+    png_image.data = match png_image.interlace_method {
+        InterlaceMethod::Adam7Interlace => {
+            reconstruct_interlaced(&decompressed, &png_image, options.custom_filter.as_deref())?
+        }
+        InterlaceMethod::NoInterlace => {
+            let (bytes_per_scanline, _) = calc_bytes_per_scanline_and_filt_offset(
+                png_image.width,
+                png_image.bit_depth,
+                &png_image.color_type,
+            )?;
+            reconstruct(
+                &decompressed,
+                png_image.height,
+                bytes_per_scanline,
+                png_image.bytes_per_pixel(),
+                options.custom_filter.as_deref(),
+            )?
+        }
+    };
+
+    if options.reduce_to_8bit && png_image.bit_depth == 16 {
+        png_image.data = reduce_samples_to_8bit(&png_image.data);
+        png_image.reduced_to_8bit();
+    }
+
+    Ok((png_image, trailing))
+}
+
+/// Parse a png image contained in the beginning of buf but stop short of
+/// decompressing the image data. Return the image with empty data together
+/// with the concatenated data of its IDAT chunks and the bytes following its
+/// IEND chunk
+pub(crate) fn parse_compressed<'a>(
+    buf: &'a [u8],
+    options: &ParseOptions,
+) -> Result<(PngImage, Vec<u8>, &'a [u8])> {
+    check_if_png(buf)?;
+    let mut crc_check = CrcCheck::new(options.on_crc_error);
+    let (mut chunks, end_idx) = parse_chunks(buf, &mut crc_check)?;
+    check_image_data_chunks(&chunks)?;
+
+    let header = header_from_chunks(&mut chunks, options)?;
+    let mut png_image = image_from_chunks(&chunks, header, options)?;
+    png_image.crc_mismatches = crc_check.mismatches;
+
+    // Collect data from all IDAT blocks into a Vec<u8>
+    let idat_data = collect_idat_data(chunks);
+    Ok((png_image, idat_data, &buf[end_idx..]))
+}
+
+/// Parse the header from the IHDR chunk, which has to be the first chunk, and
+/// check its dimensions against the size limits of the options. When
+/// recovering any chunks in front of the first IHDR are dropped first
+fn header_from_chunks(chunks: &mut Vec<Chunk>, options: &ParseOptions) -> Result<Header> {
+    if options.recovery && !options.strict {
+        if let Some(ihdr_idx) = chunks
+            .iter()
+            .position(|chunk| matches!(chunk.chunk_type, ChunkType::Ihrd))
+        {
+            chunks.drain(..ihdr_idx);
+        }
+    }
+
+    let is_ihdr = |chunk: &Chunk| matches!(chunk.chunk_type, ChunkType::Ihrd);
+    let header = match chunks.first() {
+        Some(chunk) if is_ihdr(chunk) => parse_header(chunk),
+        _ if chunks.iter().any(is_ihdr) => Err(PngError::WrongFormat(
+            "IHDR chunk is not the first chunk".to_string(),
+        )),
+        _ => Err(PngError::WrongFormat("Missing IHDR chunk".to_string())),
+    }?;
+
+    let pixels = header.width as u64 * header.height as u64;
+    if options.max_width.is_some_and(|max| header.width > max)
+        || options.max_height.is_some_and(|max| header.height > max)
+        || options.max_pixels.is_some_and(|max| pixels > max)
+    {
+        return Err(PngError::NotSupported(format!(
+            "Image of {}x{} pixels exceeds the size limits",
+            header.width, header.height
+        )));
+    }
+    Ok(header)
+}
+
+/// Build an image with empty data from the header and the metadata found in
+/// the chunks, the data of IDAT chunks is not used
+fn image_from_chunks(chunks: &[Chunk], header: Header, options: &ParseOptions) -> Result<PngImage> {
+    let Header {
+        width,
+        height,
+        bit_depth,
+        color_type,
+        compression_method,
+        filter_method,
+        interlace_method,
+    } = header;
+
+    let ihdr_count = chunks
+        .iter()
+        .filter(|chunk| matches!(chunk.chunk_type, ChunkType::Ihrd))
+        .count();
+    if ihdr_count > 1 {
+        return Err(PngError::WrongFormat("Multiple IHDR chunks".to_string()));
+    }
+
+    if options.strict {
+        check_chunk_lengths(chunks, &color_type)?;
+        check_critical_chunk_order(chunks)?;
+    }
+
+    // A palette is only required for indexed color images, for truecolor
+    // images it is merely a suggestion and grayscale images should have none
+    let mut palette = parse_palette(chunks)?;
+    if matches!(color_type, ColorType::IndexedColor) && palette.is_none() {
+        return Err(PngError::WrongFormat(
+            "Indexed color image has no PLTE chunk".to_string(),
+        ));
+    }
+    let offset = parse_offset(chunks)?;
+    let scale = parse_scale(chunks)?;
+    let gamma = parse_gamma(chunks)?;
+    let srgb_intent = parse_srgb(chunks)?;
+    let frames = parse_frames(chunks)?;
+    let animation_control = parse_animation_control(chunks)?;
+    let transparency = parse_transparency(chunks, &color_type, palette.as_mut())?;
+    let text = parse_text(chunks)?;
+    let physical_dimensions = parse_physical_dimensions(chunks)?;
+    let background = parse_background(chunks, &color_type, bit_depth, palette.as_deref())?;
+    let significant_bits = parse_significant_bits(chunks, &color_type, bit_depth)?;
+    let modification_time = parse_time(chunks)?;
+    let histogram = parse_histogram(chunks, palette.as_deref())?;
+    let icc_profile = parse_icc_profile(chunks)?;
+    let ancillary_chunks = collect_ancillary_names(chunks);
+
+    Ok(PngImage {
+        width,
+        height,
+        bit_depth,
+        color_type,
+        compression_method,
+        filter_method,
+        interlace_method,
+        palette,
+        data: Vec::new(),
+        offset,
+        scale,
+        gamma,
+        srgb_intent,
+        frames,
+        animation_control,
+        transparency,
+        text,
+        physical_dimensions,
+        background,
+        significant_bits,
+        modification_time,
+        histogram,
+        icc_profile,
+        raw_idat: None,
+        ancillary_chunks,
+        crc_mismatches: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    /// Parse a 1x1 8-bit grayscale image with the extra chunks in front of
+    /// its image data
+    fn parse_with_chunks(extra: &[Vec<u8>]) -> Result<PngImage> {
+        parse_png_bytes(&test_util::png(1, 1, 8, 0, &[0, 0], extra))
+    }
+
+    #[test]
+    fn unknown_compression_methods_are_unsupported() {
+        let ztxt = test_util::chunk(b"zTXt", b"Comment\0\x01data");
+        assert!(matches!(
+            parse_with_chunks(&[ztxt]),
+            Err(PngError::UnsupportedCompression(1))
+        ));
+
+        let itxt = test_util::chunk(b"iTXt", b"Comment\0\x01\x01en\0\0data");
+        assert!(matches!(
+            parse_with_chunks(&[itxt]),
+            Err(PngError::UnsupportedCompression(1))
+        ));
+
+        let iccp = test_util::chunk(b"iCCP", b"Profile\0\x01data");
+        assert!(matches!(
+            parse_with_chunks(&[iccp]),
+            Err(PngError::UnsupportedCompression(1))
+        ));
+    }
+
+    #[test]
+    fn icc_profile_is_decompressed() {
+        let mut data = b"sRGB profile\0\0".to_vec();
+        data.extend(test_util::zlib(b"profile bytes"));
+        let png_image = parse_with_chunks(&[test_util::chunk(b"iCCP", &data)]).unwrap();
+
+        assert_eq!(
+            png_image.icc_profile(),
+            Some(("sRGB profile", &b"profile bytes"[..]))
+        );
+    }
+
+    /// Parse the file from a buffer and from a reader, with and without
+    /// strict checks, and return the error of each
+    fn errors_of_both_paths(buf: &[u8]) -> Vec<String> {
+        [false, true]
+            .into_iter()
+            .flat_map(|strict| {
+                let options = ParseOptions {
+                    strict,
+                    ..Default::default()
+                };
+                [
+                    parse_png_bytes_with_options(buf, &options).map(|_| ()),
+                    parse_png_from_reader(buf, &options).map(|_| ()),
+                ]
+            })
+            .map(|res| format!("{:?}", res.unwrap_err()))
+            .collect()
+    }
+
+    #[test]
+    fn reader_and_buffer_reject_non_consecutive_idat() {
+        let buf = test_util::file(&[
+            test_util::ihdr(1, 1, 8, 0, 0),
+            test_util::chunk(b"IDAT", &test_util::zlib(&[0, 0])[..4]),
+            test_util::chunk(b"tEXt", b"Comment\0between"),
+            test_util::chunk(b"IDAT", &test_util::zlib(&[0, 0])[4..]),
+            test_util::chunk(b"IEND", &[]),
+        ]);
+        let errors = errors_of_both_paths(&buf);
+
+        assert!(errors.iter().all(|err| err.contains("not consecutive")));
+    }
+
+    #[test]
+    fn reader_and_buffer_reject_iend_before_idat() {
+        let buf = test_util::file(&[
+            test_util::ihdr(1, 1, 8, 0, 0),
+            test_util::chunk(b"IEND", &[]),
+            test_util::idat(&[0, 0]),
+        ]);
+        let errors = errors_of_both_paths(&buf);
+
+        assert!(errors.iter().all(|err| err == &errors[0]), "{:?}", errors);
+    }
+
+    #[test]
+    fn reader_and_buffer_agree_on_oversized_dimensions() {
+        let buf = test_util::file(&[
+            test_util::ihdr(2_000_000, 2_000_000, 8, 6, 0),
+            test_util::idat(&[0, 0, 0, 0, 0]),
+            test_util::chunk(b"IEND", &[]),
+        ]);
+        let errors = errors_of_both_paths(&buf);
+
+        assert!(
+            errors.iter().all(|err| err == "TruncatedData"),
+            "{:?}",
+            errors
+        );
+    }
+
+    #[test]
+    fn interlaced_image_needs_adam7_feature() {
+        let interlaced = test_util::file(&[test_util::ihdr(1, 1, 8, 0, 1)]);
+        let expected = match cfg!(feature = "adam7") {
+            true => vec![],
+            false => vec![Capability::Adam7Interlacing],
+        };
+        assert_eq!(missing_capabilities(&interlaced).unwrap(), expected);
+
+        let plain = test_util::file(&[test_util::ihdr(1, 1, 8, 0, 0)]);
+        assert!(missing_capabilities(&plain).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reduce_to_8bit_keeps_most_significant_byte() {
+        let bytes = test_util::png(2, 1, 16, 0, &[0, 0x12, 0xFF, 0xAB, 0x01], &[]);
+        let options = ParseOptions {
+            reduce_to_8bit: true,
+            ..Default::default()
+        };
+        let reduced = parse_png_bytes_with_options(&bytes, &options).unwrap();
+
+        assert_eq!(reduced.bit_depth, 8);
+        assert_eq!(reduced.data, vec![0x12, 0xAB]);
+        assert_eq!(
+            crate::decoder::decode_png(&reduced).unwrap(),
+            crate::decoder::decode_png(&parse_png_bytes(&bytes).unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn scale_is_parsed() {
+        let png_image = parse_with_chunks(&[test_util::chunk(b"sCAL", b"\x011.5\x002.0")]).unwrap();
+        let scale = png_image.scale().unwrap();
+
+        assert!(matches!(scale.unit, ScaleUnit::Metre));
+        assert_eq!(
+            (scale.width.as_str(), scale.height.as_str()),
+            ("1.5", "2.0")
+        );
+        assert_eq!((scale.width_value(), scale.height_value()), (1.5, 2.0));
+    }
+
+    #[test]
+    fn summary_contains_dimensions_and_color_type() {
+        let bytes = test_util::png(
+            3,
+            2,
+            8,
+            2,
+            &[0; 20],
+            &[test_util::chunk(b"gAMA", &[0, 0, 0xB1, 0x8F])],
+        );
+        let summary = parse_png_bytes(&bytes).unwrap().summary();
+
+        assert!(summary.contains("Dimensions: 3x2"));
+        assert!(summary.contains("Color type: Truecolor"));
+        assert!(summary.contains("Ancillary chunks: gAMA"));
+    }
+
+    #[test]
+    fn drop_alpha_keeps_rgb_pixels() {
+        let filtered = [0, 10, 20, 30, 40, 50, 60, 70, 80];
+        let png_image = parse_png_bytes(&test_util::png(2, 1, 8, 6, &filtered, &[])).unwrap();
+        let rgba = crate::decoder::decode_png(&png_image).unwrap();
+
+        let dropped = png_image.drop_alpha();
+        assert!(matches!(dropped.color_type(), ColorType::Truecolor));
+        assert_eq!(dropped.data, vec![10, 20, 30, 50, 60, 70]);
+
+        let rgb = crate::decoder::decode_png(&dropped).unwrap();
+        for (with_alpha, without) in rgba[0].iter().zip(rgb[0].iter()) {
+            assert_eq!(
+                Color {
+                    alpha: 255,
+                    ..*with_alpha
+                },
+                *without
+            );
+        }
+    }
+
+    #[test]
+    fn wrong_length_gamma_fails_only_in_strict_mode() {
+        let bytes = test_util::png(1, 1, 8, 0, &[0, 0], &[test_util::chunk(b"gAMA", &[0; 5])]);
+        let options = ParseOptions {
+            strict: true,
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            parse_png_bytes_with_options(&bytes, &options),
+            Err(PngError::WrongFormat(_))
+        ));
+        let png_image = parse_png_bytes_with_options(&bytes, &ParseOptions::default()).unwrap();
+        assert_eq!(png_image.gamma(), None);
+        assert_eq!(
+            expected_length(&ChunkType::Gama, &ColorType::Grayscale),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn strict_mode_rejects_out_of_place_critical_chunks() {
+        let options = ParseOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let error_of = |chunks: &[Vec<u8>]| match parse_png_bytes_with_options(
+            &test_util::file(chunks),
+            &options,
+        ) {
+            Err(PngError::WrongFormat(message)) => message,
+            res => panic!("Expected WrongFormat, got {:?}", res.map(|_| ())),
+        };
+
+        let iend_first = error_of(&[
+            test_util::ihdr(1, 1, 8, 0, 0),
+            test_util::chunk(b"IEND", &[]),
+            test_util::idat(&[0, 0]),
+        ]);
+        assert_eq!(iend_first, "Missing IDAT chunk");
+
+        let two_ihdrs = error_of(&[
+            test_util::ihdr(1, 1, 8, 0, 0),
+            test_util::ihdr(1, 1, 8, 0, 0),
+            test_util::idat(&[0, 0]),
+            test_util::chunk(b"IEND", &[]),
+        ]);
+        assert_eq!(two_ihdrs, "Multiple IHDR chunks");
+
+        let late_palette = error_of(&[
+            test_util::ihdr(1, 1, 8, 2, 0),
+            test_util::idat(&[0, 0, 0, 0]),
+            test_util::chunk(b"PLTE", &[0, 0, 0]),
+            test_util::chunk(b"IEND", &[]),
+        ]);
+        assert_eq!(late_palette, "PLTE chunk out of place");
+    }
+
+    #[test]
+    fn srgb_takes_precedence_over_gamma() {
+        let gama = test_util::chunk(b"gAMA", &45455_u32.to_be_bytes());
+        let srgb = test_util::chunk(b"sRGB", &[0]);
+
+        let both = parse_with_chunks(&[gama.clone(), srgb]).unwrap();
+        assert_eq!(both.effective_transfer(), Transfer::Srgb);
+
+        let gamma_only = parse_with_chunks(&[gama]).unwrap();
+        assert_eq!(gamma_only.effective_transfer(), Transfer::Gamma(0.45455));
+
+        let neither = parse_with_chunks(&[]).unwrap();
+        assert_eq!(neither.effective_transfer(), Transfer::Unknown);
+    }
+
+    #[test]
+    fn metadata_is_read_from_ihdr_only_file() {
+        let bytes = test_util::file(&[test_util::ihdr(7, 3, 16, 2, 0)]);
+        let info = read_metadata(&bytes).unwrap();
+
+        assert_eq!((info.width, info.height, info.bit_depth), (7, 3, 16));
+        assert!(matches!(info.color_type, ColorType::Truecolor));
+        assert!(!info.interlaced);
+        assert!(parse_png_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn custom_filter_is_invoked_for_unknown_filter_type() {
+        let bytes = test_util::png(2, 1, 8, 0, &[5, 0x0F, 0xF0], &[]);
+        assert!(matches!(
+            parse_png_bytes(&bytes),
+            Err(PngError::FilterNotSupported(5))
+        ));
+
+        let options = ParseOptions {
+            custom_filter: Some(Box::new(|filter_type, x, _, _, _| match filter_type {
+                5 => !x,
+                _ => x,
+            })),
+            ..Default::default()
+        };
+        let png_image = parse_png_bytes_with_options(&bytes, &options).unwrap();
+        assert_eq!(png_image.data, vec![0xF0, 0x0F]);
+    }
+
+    #[test]
+    fn zlib_window_above_32k_is_rejected() {
+        let mut data = test_util::zlib(&[0, 0]);
+        // CINFO 8 in the upper nibble, with the check bits of the flags fixed
+        data[0] = 0x88;
+        data[1] = (31 - (0x88_u16 * 256 + (data[1] & 0xE0) as u16) % 31) as u8 | (data[1] & 0xE0);
+        let bytes = test_util::file(&[
+            test_util::ihdr(1, 1, 8, 0, 0),
+            test_util::chunk(b"IDAT", &data),
+            test_util::chunk(b"IEND", &[]),
+        ]);
+
+        for res in [
+            parse_png_bytes(&bytes),
+            parse_png_from_reader(bytes.as_slice(), &ParseOptions::default()),
+        ] {
+            match res {
+                Err(PngError::WrongFormat(message)) => assert_eq!(message, "zlib window too large"),
+                res => panic!("Expected WrongFormat, got {:?}", res.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn frame_delay_in_milliseconds() {
+        let frame = Frame {
+            sequence_number: 0,
+            width: 1,
+            height: 1,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 10,
+            dispose_op: 0,
+            blend_op: 0,
+        };
+        assert_eq!(frame.delay_ms(), 100.0);
+
+        let hundredths = Frame {
+            delay_num: 7,
+            delay_den: 0,
+            ..frame
+        };
+        assert_eq!(hundredths.delay_ms(), 70.0);
+    }
+
+    #[test]
+    fn leading_junk_chunk_is_skipped_in_recovery_mode() {
+        let bytes = test_util::file(&[
+            test_util::chunk(b"juNK", b"garbage"),
+            test_util::ihdr(1, 1, 8, 0, 0),
+            test_util::idat(&[0, 42]),
+            test_util::chunk(b"IEND", &[]),
+        ]);
+        let recovery = ParseOptions {
+            recovery: true,
+            ..Default::default()
+        };
+        let strict_recovery = ParseOptions {
+            recovery: true,
+            strict: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            parse_png_bytes_with_options(&bytes, &recovery)
+                .unwrap()
+                .data,
+            vec![42]
+        );
+        assert!(parse_png_bytes(&bytes).is_err());
+        assert!(parse_png_bytes_with_options(&bytes, &strict_recovery).is_err());
+    }
+
+    #[test]
+    fn truecolor_image_is_parsed_without_palette() {
+        let filtered = [0, 255, 0, 0, 0, 128, 64, 0, 0, 0, 255, 1, 2, 3];
+        let png_image = parse_png_bytes(&test_util::png(2, 2, 8, 2, &filtered, &[])).unwrap();
+        assert!(png_image.palette.is_none());
+
+        let decoded = crate::decoder::decode_png(&png_image).unwrap();
+        let rgb = |pixel: &Color| (pixel.red, pixel.green, pixel.blue);
+        assert_eq!(rgb(&decoded[0][0]), (255, 0, 0));
+        assert_eq!(rgb(&decoded[0][1]), (0, 128, 64));
+        assert_eq!(rgb(&decoded[1][1]), (1, 2, 3));
+    }
+
+    #[test]
+    fn sub_filter_uses_previous_pixel_of_rgb_image() {
+        let filtered = [1, 10, 20, 30, 5, 5, 5, 5, 15, 25];
+        let png_image = parse_png_bytes(&test_util::png(3, 1, 8, 2, &filtered, &[])).unwrap();
+
+        assert_eq!(png_image.data, vec![10, 20, 30, 15, 25, 35, 20, 40, 60]);
+    }
+
+    #[test]
+    fn short_buffer_is_not_a_png() {
+        assert!(matches!(
+            check_if_png(&[0x89, 0x50]),
+            Err(PngError::NotAPng)
+        ));
+        assert!(matches!(
+            parse_png_bytes(&[0x89, 0x50]),
+            Err(PngError::NotAPng)
+        ));
+    }
+
+    #[test]
+    fn missing_iend_is_an_error() {
+        let bytes = test_util::file(&[test_util::ihdr(1, 1, 8, 0, 0), test_util::idat(&[0, 0])]);
+
+        for res in [
+            parse_png_bytes(&bytes),
+            parse_png_from_reader(bytes.as_slice(), &ParseOptions::default()),
+        ] {
+            match res {
+                Err(PngError::WrongFormat(message)) => assert_eq!(message, "Missing IEND chunk"),
+                res => panic!("Expected WrongFormat, got {:?}", res.map(|_| ())),
+            }
+        }
+    }
+
+    /// The bit-by-bit crc32 the lookup table replaced
+    fn crc32_bitwise(seq: &[u8]) -> u32 {
+        let mut crc: u32 = 0xFFFFFFFF;
+
+        for ch in seq.iter() {
+            let mut c = *ch as u32;
+            for _ in 0..8 {
+                let b = (c ^ crc) & 1;
+                crc >>= 1;
+                if b > 0 {
+                    crc ^= 0xEDB88320;
+                }
+                c >>= 1;
+            }
+        }
+        !crc
+    }
+
+    #[test]
+    fn table_crc32_matches_bitwise_crc32() {
+        let all_bytes: Vec<u8> = (0..=255).collect();
+        let sequences: [&[u8]; 5] = [b"", b"IEND", b"123456789", &[0xFF; 100], &all_bytes];
+
+        for seq in sequences {
+            assert_eq!(crc32(seq), crc32_bitwise(seq));
+        }
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b"IEND"), 0xAE426082);
+    }
+
+    #[test]
+    fn average_and_up_filters_reconstruct_exactly() {
+        // One row each filtered with Average, Average and Up by an independent
+        // encoder
+        let filtered = [
+            [3, 200, 100, 50, 110, 70, 35, 150, 195, 225],
+            [3, 166, 200, 103, 146, 71, 162, 1, 193, 161],
+            [2, 247, 8, 131, 250, 251, 252, 135, 199, 231],
+        ]
+        .concat();
+        let png_image = parse_png_bytes(&test_util::png(3, 3, 8, 2, &filtered, &[])).unwrap();
+
+        let expected = [
+            [200, 100, 50, 210, 120, 60, 255, 255, 255],
+            [10, 250, 128, 0, 0, 0, 128, 64, 32],
+            [1, 2, 3, 250, 251, 252, 7, 7, 7],
+        ]
+        .concat();
+        assert_eq!(png_image.data, expected);
+    }
+
+    #[test]
+    fn filters_match_spec_vectors() {
+        assert_eq!(apply_none(77), 77);
+
+        assert_eq!(apply_sub(10, 20), 30);
+        assert_eq!(apply_sub(10, 250), 4);
+
+        assert_eq!(apply_up(200, 100), 44);
+
+        assert_eq!(apply_average(0, 3, 4), 3);
+        assert_eq!(apply_average(5, 255, 255), 4);
+        assert_eq!(apply_average(1, 255, 1), 129);
+
+        // Predictor picks c, b, a and b again
+        assert_eq!(apply_paeth(1, 10, 20, 15), 16);
+        assert_eq!(apply_paeth(1, 10, 20, 10), 21);
+        assert_eq!(apply_paeth(1, 20, 10, 10), 21);
+        assert_eq!(apply_paeth(1, 30, 10, 40), 11);
+        assert_eq!(apply_paeth(250, 10, 20, 15), 9);
+    }
+
+    #[test]
+    fn raw_idat_is_kept_on_request() {
+        let compressed = test_util::zlib(&[0, 1, 2]);
+        let bytes = test_util::file(&[
+            test_util::ihdr(2, 1, 8, 0, 0),
+            test_util::chunk(b"IDAT", &compressed[..3]),
+            test_util::chunk(b"IDAT", &compressed[3..]),
+            test_util::chunk(b"IEND", &[]),
+        ]);
+        let options = ParseOptions {
+            keep_raw_idat: true,
+            ..Default::default()
+        };
+
+        for png_image in [
+            parse_png_bytes_with_options(&bytes, &options).unwrap(),
+            parse_png_from_reader(bytes.as_slice(), &options).unwrap(),
+        ] {
+            assert_eq!(png_image.raw_idat(), Some(compressed.as_slice()));
+        }
+        assert!(parse_png_bytes(&bytes).unwrap().raw_idat().is_none());
+    }
+
+    #[cfg(feature = "adam7")]
+    /// Return the scanlines holding the pixels at columns xs of rows ys, each
+    /// pixel given by pixel as a value of bits_per_pixel bits, packed and
+    /// filtered with Sub. Rows are left out if xs is empty
+    fn sub_filtered_scanlines(
+        xs: &[usize],
+        ys: &[usize],
+        bits_per_pixel: usize,
+        pixel: &dyn Fn(usize, usize) -> u64,
+    ) -> Vec<u8> {
+        let mut res: Vec<u8> = Vec::new();
+        if xs.is_empty() {
+            return res;
+        }
+
+        for &y in ys {
+            let mut raw = vec![0_u8; (xs.len() * bits_per_pixel).div_ceil(8)];
+            for (idx, &x) in xs.iter().enumerate() {
+                let value = pixel(x, y);
+                for bit in 0..bits_per_pixel {
+                    if value >> (bits_per_pixel - 1 - bit) & 1 == 1 {
+                        let pos = idx * bits_per_pixel + bit;
+                        raw[pos / 8] |= 0x80 >> (pos % 8);
+                    }
+                }
+            }
+
+            let offset = (bits_per_pixel / 8).max(1);
+            res.push(1);
+            res.extend(
+                (0..raw.len())
+                    .map(|i| raw[i].wrapping_sub(if i >= offset { raw[i - offset] } else { 0 })),
+            );
+        }
+        res
+    }
+
+    #[test]
+    #[cfg(feature = "adam7")]
+    fn interlaced_images_match_non_interlaced() {
+        // (width, height, bit depth, color type, parsed color type)
+        let formats = [
+            (1, 1, 8, 0, ColorType::Grayscale),
+            (3, 2, 8, 2, ColorType::Truecolor),
+            (5, 3, 2, 0, ColorType::Grayscale),
+            (7, 5, 1, 3, ColorType::IndexedColor),
+            (6, 7, 8, 4, ColorType::GrayScaleWithAlpha),
+            (10, 9, 16, 6, ColorType::TrueColorWithAlpha),
+            (13, 11, 4, 3, ColorType::IndexedColor),
+        ];
+
+        for (width, height, bit_depth, color_type, color) in formats {
+            let bits_per_pixel = bits_per_pixel(bit_depth, &color);
+            let pixel = |x: usize, y: usize| {
+                let hash = (x as u64 * 2654435761) ^ (y as u64 * 40503) ^ (x * y) as u64;
+                hash & (u64::MAX >> (64 - bits_per_pixel))
+            };
+            let extra = match color {
+                ColorType::IndexedColor => {
+                    vec![test_util::chunk(b"PLTE", &vec![7; 3 << bit_depth])]
+                }
+                _ => vec![],
+            };
+
+            let all_xs: Vec<usize> = (0..width).collect();
+            let all_ys: Vec<usize> = (0..height).collect();
+            let plain = sub_filtered_scanlines(&all_xs, &all_ys, bits_per_pixel, &pixel);
+
+            let mut interlaced: Vec<u8> = Vec::new();
+            for (x0, y0, dx, dy) in ADAM7_PASSES {
+                let xs: Vec<usize> = (x0..width).step_by(dx).collect();
+                let ys: Vec<usize> = (y0..height).step_by(dy).collect();
+                interlaced.extend(sub_filtered_scanlines(&xs, &ys, bits_per_pixel, &pixel));
+            }
+
+            let mut chunks = vec![test_util::ihdr(
+                width as u32,
+                height as u32,
+                bit_depth,
+                color_type,
+                1,
+            )];
+            chunks.extend(extra.iter().cloned());
+            chunks.push(test_util::idat(&interlaced));
+            chunks.push(test_util::chunk(b"IEND", &[]));
+
+            let expected = parse_png_bytes(&test_util::png(
+                width as u32,
+                height as u32,
+                bit_depth,
+                color_type,
+                &plain,
+                &extra,
+            ))
+            .unwrap();
+            for png_image in [
+                parse_png_bytes(&test_util::file(&chunks)).unwrap(),
+                parse_png_from_reader(
+                    test_util::file(&chunks).as_slice(),
+                    &ParseOptions::default(),
+                )
+                .unwrap(),
+            ] {
+                assert_eq!(png_image.data, expected.data, "{}x{}", width, height);
+                assert_eq!(
+                    crate::decoder::decode_png(&png_image).unwrap(),
+                    crate::decoder::decode_png(&expected).unwrap()
+                );
+            }
+        }
+    }
+}
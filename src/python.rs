@@ -0,0 +1,262 @@
+use pyo3::create_exception;
+use pyo3::exceptions::{PyIOError, PyNotImplementedError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::collections::HashMap;
+
+use crate::parser::{
+    parse_png, parse_png_bytes_with_options, parse_png_with_options, Color, CrcPolicy,
+    ParseOptions, PngError, PngImage, Timestamp,
+};
+use crate::{decoder, encoder, parser};
+
+/// A pixel as handed to Python
+type Rgba = (u8, u8, u8, u8);
+
+create_exception!(rust_png_reader, NotAPngError, PyValueError);
+create_exception!(
+    rust_png_reader,
+    UnsupportedFormatError,
+    PyNotImplementedError
+);
+create_exception!(rust_png_reader, CorruptPngError, PyValueError);
+
+impl From<PngError> for PyErr {
+    /// A file without the png signature raises NotAPngError, unsupported
+    /// features raise UnsupportedFormatError, files that cannot be read or
+    /// written raise OSError and a damaged file raises CorruptPngError
+    fn from(error: PngError) -> PyErr {
+        let message = error.to_string();
+        match error {
+            PngError::NotAPng => NotAPngError::new_err(message),
+            PngError::NotSupported(_)
+            | PngError::FilterNotSupported(_)
+            | PngError::UnsupportedCompression(_) => UnsupportedFormatError::new_err(message),
+            PngError::CouldNotReadFile | PngError::CouldNotWriteFile => PyIOError::new_err(message),
+            PngError::ChecksumFailure
+            | PngError::WrongFormat(_)
+            | PngError::DecompressionFailed
+            | PngError::TruncatedData => CorruptPngError::new_err(message),
+            PngError::CompressionFailed => PyValueError::new_err(message),
+        }
+    }
+}
+
+/// Checksums are either verified or not calculated at all from Python
+fn crc_policy(verify_crc: bool) -> CrcPolicy {
+    match verify_crc {
+        true => CrcPolicy::Fail,
+        false => CrcPolicy::Ignore,
+    }
+}
+
+/// Read and decode a png file and return a two-dimensional vector of RGBA
+/// values. If background is given as an (r, g, b) tuple transparent pixels are
+/// flattened over that color and every alpha is 255. Chunk checksums are not
+/// verified if verify_crc is false. Errors are raised as Python exceptions
+#[pyfunction(background = "None", verify_crc = "true")]
+fn read_png(
+    path: String,
+    background: Option<(u8, u8, u8)>,
+    verify_crc: bool,
+) -> PyResult<Vec<Vec<Rgba>>> {
+    let options = ParseOptions {
+        on_crc_error: crc_policy(verify_crc),
+        ..ParseOptions::default()
+    };
+    decode_to_rgba_rows(&parse_png_with_options(&path, &options)?, background)
+}
+
+/// Same as read_png but decodes png data already in memory, such as the body
+/// of an HTTP response, instead of reading a file
+#[pyfunction(background = "None", verify_crc = "true")]
+fn read_png_bytes(
+    data: &[u8],
+    background: Option<(u8, u8, u8)>,
+    verify_crc: bool,
+) -> PyResult<Vec<Vec<Rgba>>> {
+    let options = ParseOptions {
+        on_crc_error: crc_policy(verify_crc),
+        ..ParseOptions::default()
+    };
+    decode_to_rgba_rows(&parse_png_bytes_with_options(data, &options)?, background)
+}
+
+/// Read and decode a png file into a flat bytes object of 8-bit samples and
+/// return it with the width, the height and the number of channels, so that
+/// it can be loaded with numpy.frombuffer(data, numpy.uint8).reshape(h, w, c)
+#[pyfunction(verify_crc = "true")]
+fn read_png_flat(
+    py: Python<'_>,
+    path: String,
+    verify_crc: bool,
+) -> PyResult<(Py<PyBytes>, usize, usize, usize)> {
+    let options = ParseOptions {
+        on_crc_error: crc_policy(verify_crc),
+        ..ParseOptions::default()
+    };
+    let png_image = parse_png_with_options(&path, &options)?;
+    let (data, width, height, channels) = decoder::decode_png_flat(&png_image)?;
+    Ok((PyBytes::new(py, &data).into(), width, height, channels))
+}
+
+/// Read an indexed color png file and return the palette index of every pixel,
+/// row by row, and the palette as RGBA tuples. Raises NotImplementedError for
+/// images that are not indexed
+#[pyfunction]
+fn read_png_indexed(path: String) -> PyResult<(Vec<Vec<u8>>, Vec<Rgba>)> {
+    let png_image = parse_png(&path)?;
+    let (indices, palette) = decoder::decode_indexed(&png_image)?;
+    let palette = palette
+        .iter()
+        .map(|color| (color.red, color.green, color.blue, color.alpha))
+        .collect();
+    Ok((indices, palette))
+}
+
+/// Read the tEXt, zTXt and iTXt chunks of a png file and return them as a dict from
+/// keyword to text. If a keyword occurs more than once the last text is kept
+#[pyfunction]
+fn read_png_text(path: String) -> PyResult<HashMap<String, String>> {
+    let png_image = parse_png(&path)?;
+    Ok(png_image
+        .text()
+        .iter()
+        .map(|chunk| (chunk.keyword.clone(), chunk.text.clone()))
+        .collect())
+}
+
+/// Read the resolution of a png file from its pHYs chunk and return it as
+/// (x, y) dots per inch, or None if the file has no resolution in meters
+#[pyfunction]
+fn read_png_dpi(path: String) -> PyResult<Option<(f64, f64)>> {
+    let png_image = parse_png(&path)?;
+    Ok(png_image.dpi())
+}
+
+/// Read the rendering intent of the sRGB chunk of a png file, or None if the
+/// file does not declare that it is sRGB
+#[pyfunction]
+fn read_png_srgb_intent(path: String) -> PyResult<Option<u8>> {
+    let png_image = parse_png(&path)?;
+    Ok(png_image.srgb_intent())
+}
+
+/// Read the time of the last modification from the tIME chunk of a png file
+/// as (year, month, day, hour, minute, second), or None if the file has none
+#[pyfunction]
+fn read_png_time(path: String) -> PyResult<Option<Timestamp>> {
+    let png_image = parse_png(&path)?;
+    Ok(png_image.modification_time())
+}
+
+/// Save rows of RGBA tuples as a png file at path
+#[pyfunction]
+fn write_png(path: String, pixels: Vec<Vec<Rgba>>) -> PyResult<()> {
+    encoder::save_png(&path, &from_rgba_rows(&pixels))?;
+    Ok(())
+}
+
+/// Return the w x h region of rows of RGBA tuples, as returned by read_png,
+/// whose top left pixel is at column x and row y
+#[pyfunction]
+fn crop_png(
+    pixels: Vec<Vec<Rgba>>,
+    x: usize,
+    y: usize,
+    w: usize,
+    h: usize,
+) -> PyResult<Vec<Vec<Rgba>>> {
+    let cropped = decoder::crop(&from_rgba_rows(&pixels), x, y, w, h)?;
+    Ok(to_rgba_rows(&cropped))
+}
+
+/// Convert rows of RGBA tuples from Python into rows of pixels
+fn from_rgba_rows(pixels: &[Vec<Rgba>]) -> Vec<Vec<Color>> {
+    pixels
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|&(red, green, blue, alpha)| Color {
+                    red,
+                    green,
+                    blue,
+                    alpha,
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Convert rows of pixels into rows of RGBA tuples for Python
+fn to_rgba_rows(pixels: &[Vec<Color>]) -> Vec<Vec<Rgba>> {
+    pixels
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|pixel| (pixel.red, pixel.green, pixel.blue, pixel.alpha))
+                .collect()
+        })
+        .collect()
+}
+
+/// Read only the header of a png file and return its width, height, bit depth
+/// and the name of its color type without decoding any pixels
+#[pyfunction]
+fn read_png_info(path: String) -> PyResult<(u32, u32, u8, String)> {
+    let info = parser::read_png_info(&path)?;
+    Ok((
+        info.width,
+        info.height,
+        info.bit_depth,
+        info.color_type.to_string(),
+    ))
+}
+
+/// Decode a parsed png image into rows of RGBA tuples for the Python bindings,
+/// flattening transparent pixels over background if it is given
+fn decode_to_rgba_rows(
+    png_image: &PngImage,
+    background: Option<(u8, u8, u8)>,
+) -> PyResult<Vec<Vec<Rgba>>> {
+    // decode_png checks that the grid is rectangular and matches the header,
+    // so Python never receives a misshapen grid
+    let mut img = decoder::decode_png(png_image)?.into_rows();
+
+    if let Some((red, green, blue)) = background {
+        let bg = Color {
+            red,
+            green,
+            blue,
+            alpha: 255,
+        };
+        img = decoder::composite_over(&img, bg);
+    }
+
+    Ok(to_rgba_rows(&img))
+}
+
+/// A Python module implemented in Rust. The name of this function must match
+/// the `lib.name` setting in the `Cargo.toml`, else Python will not be able to
+/// import the module.
+#[pymodule]
+fn rust_png_reader(py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add("NotAPngError", py.get_type::<NotAPngError>())?;
+    m.add(
+        "UnsupportedFormatError",
+        py.get_type::<UnsupportedFormatError>(),
+    )?;
+    m.add("CorruptPngError", py.get_type::<CorruptPngError>())?;
+    m.add_function(wrap_pyfunction!(read_png, m)?)?;
+    m.add_function(wrap_pyfunction!(read_png_bytes, m)?)?;
+    m.add_function(wrap_pyfunction!(read_png_flat, m)?)?;
+    m.add_function(wrap_pyfunction!(read_png_indexed, m)?)?;
+    m.add_function(wrap_pyfunction!(read_png_info, m)?)?;
+    m.add_function(wrap_pyfunction!(read_png_text, m)?)?;
+    m.add_function(wrap_pyfunction!(read_png_dpi, m)?)?;
+    m.add_function(wrap_pyfunction!(read_png_srgb_intent, m)?)?;
+    m.add_function(wrap_pyfunction!(read_png_time, m)?)?;
+    m.add_function(wrap_pyfunction!(write_png, m)?)?;
+    m.add_function(wrap_pyfunction!(crop_png, m)?)?;
+    Ok(())
+}
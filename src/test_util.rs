@@ -0,0 +1,61 @@
+use crate::parser::crc32;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::Write;
+
+pub const SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// Return a complete chunk with length, type, data and a correct checksum
+pub fn chunk(chunk_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut res = (data.len() as u32).to_be_bytes().to_vec();
+    res.extend_from_slice(chunk_type);
+    res.extend_from_slice(data);
+    let crc = crc32(&res[4..]);
+    res.extend_from_slice(&crc.to_be_bytes());
+    res
+}
+
+/// Return an IHDR chunk with compression and filter method 0
+pub fn ihdr(width: u32, height: u32, bit_depth: u8, color_type: u8, interlace: u8) -> Vec<u8> {
+    let mut data = width.to_be_bytes().to_vec();
+    data.extend_from_slice(&height.to_be_bytes());
+    data.extend_from_slice(&[bit_depth, color_type, 0, 0, interlace]);
+    chunk(b"IHDR", &data)
+}
+
+/// Compress data into a zlib stream
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Return an IDAT chunk holding the compressed filtered scanlines, which
+/// include their filter bytes
+pub fn idat(filtered: &[u8]) -> Vec<u8> {
+    chunk(b"IDAT", &zlib(filtered))
+}
+
+/// Return the png signature followed by the chunks
+pub fn file(chunks: &[Vec<u8>]) -> Vec<u8> {
+    let mut res = SIGNATURE.to_vec();
+    chunks.iter().for_each(|chunk| res.extend_from_slice(chunk));
+    res
+}
+
+/// Return a non-interlaced png made of IHDR, the extra chunks, a single IDAT
+/// with the filtered scanlines and IEND
+pub fn png(
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: u8,
+    filtered: &[u8],
+    extra: &[Vec<u8>],
+) -> Vec<u8> {
+    let mut chunks = vec![ihdr(width, height, bit_depth, color_type, 0)];
+    chunks.extend_from_slice(extra);
+    chunks.push(idat(filtered));
+    chunks.push(chunk(b"IEND", &[]));
+    file(&chunks)
+}